@@ -0,0 +1,386 @@
+use crate::held_karp;
+use crate::optimizer::{OptimizedOrder, TourOptimizer, TourProblem};
+use crate::tour::{Move, Tour};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// How many random moves permute tries for one that keeps pairs in order before counting a miss.
+const PERMUTE_ATTEMPTS: usize = 32;
+// How many chains a seeded optimizer runs when not told otherwise.
+const SEEDED_THREADS: usize = 4;
+// How often, in moves, a chain looks at the clock.
+const CLOCK_CHECK_MOVES: usize = 256;
+
+// * Everything that trades plan quality against how long planning takes.
+#[derive(Clone, Debug)]
+pub struct OptimizerConfig {
+    pub cooling: Cooling,
+    pub acceptance: Acceptance,
+    pub initial_temperature: f64,
+    // Moves in a row without a better tour before a chain starts over from its best.
+    // Without one, it depends on how many stops there are.
+    pub patience: Option<usize>,
+    // How many times a chain starts over from its best tour.
+    pub restarts: usize,
+    // * A chain stops at whichever of these runs out first, even mid-restart.
+    pub max_moves: Option<usize>,
+    pub time_limit: Option<Duration>,
+    // Annealing chains to run side by side.  Without a number, one per core, or 4 when seeded.
+    pub threads: Option<usize>,
+    // The same stops and seed always come out in the same order, unless a time limit cuts chains short.
+    pub seed: Option<u64>,
+    // Up to this many stops, tours are solved exactly rather than annealed.
+    // Never more than held_karp::MAX_STOPS, whatever it's set to.
+    pub exact_threshold: usize,
+}
+
+impl Default for OptimizerConfig {
+    fn default() -> Self {
+        OptimizerConfig {
+            cooling: Cooling::Geometric { factor: 0.99 },
+            acceptance: Acceptance::Threshold,
+            initial_temperature: 0.9,
+            patience: None,
+            restarts: 100,
+            max_moves: None,
+            time_limit: None,
+            threads: None,
+            seed: None,
+            exact_threshold: 12,
+        }
+    }
+}
+
+// * How the temperature falls each time a move fails to improve the tour.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Cooling {
+    // Multiply by factor.
+    Geometric { factor: f64 },
+    // Subtract step, stopping at zero.
+    Linear { step: f64 },
+    // Geometric, but after stall moves without a better tour, heat back up to the temperature
+    // the last better tour was found at.
+    Reheating { factor: f64, stall: usize },
+}
+
+// * When a chain takes a move that makes its tour worse.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Acceptance {
+    // With the temperature as the probability, however much worse the move is.
+    Threshold,
+    // With probability e^(-delta / temperature), so the temperature is in miles and small
+    // setbacks are taken far more often than big ones.
+    Metropolis,
+}
+
+// * How one annealing chain went, for tuning.
+#[derive(Clone, Debug, Default)]
+pub struct ChainStats {
+    pub thread: usize,
+    // Moves proposed, moves taken, and how many of those taken made the tour worse.
+    pub moves: usize,
+    pub accepted: usize,
+    pub uphill: usize,
+    // * Times every move permute tried would have dropped an item off before its pickup.
+    pub missed: usize,
+    pub best_cost: f64,
+    pub elapsed: Duration,
+}
+
+impl std::fmt::Display for ChainStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "Thread {}: {} moves, {} accepted ({} uphill), {} missed, best {:.2} in {:.3}s",
+            self.thread,
+            self.moves,
+            self.accepted,
+            self.uphill,
+            self.missed,
+            self.best_cost,
+            self.elapsed.as_secs_f64()
+        )
+    }
+}
+
+// * Simulated annealing over 2-opt, Or-opt and 3-opt moves, with independent chains on several
+// * threads.  Small tours that only cost their length are solved exactly instead.
+pub struct SimulatedAnnealing {
+    config: OptimizerConfig,
+}
+
+impl TourOptimizer for SimulatedAnnealing {
+    // * Small tours scored by distance alone are solved exactly; the rest are annealed.
+    // * Lateness and reloads depend on more than the last stop, which the exact solver can't track.
+    fn optimize(&self, problem: &TourProblem) -> OptimizedOrder {
+        let stops = problem.stop_count();
+        let exact_threshold = self.config.exact_threshold.min(held_karp::MAX_STOPS);
+        if stops <= exact_threshold && problem.depends_only_on_length() {
+            if let Some((order, _)) = held_karp::solve(problem.costs(), stops, &problem.pickup_nodes()) {
+                return OptimizedOrder {
+                    deliveries: problem.stops_in(&order),
+                    optimal: true,
+                    stats: Vec::new(),
+                };
+            }
+        }
+        // * Independent chains share the problem and keep to themselves otherwise, so the best of
+        // * them can simply be picked once they're all done.  Ties go to the lowest thread.
+        let chains: Vec<(Tour, f64, ChainStats)> = thread::scope(|scope| {
+            let workers: Vec<_> = (0..self.thread_count())
+                .map(|thread| scope.spawn(move || self.chain(problem, thread)))
+                .collect();
+            workers
+                .into_iter()
+                .map(|worker| worker.join().unwrap())
+                .collect()
+        });
+        let mut stats = Vec::new();
+        let mut best: Option<Tour> = None;
+        let mut best_cost = f64::INFINITY;
+        for (tour, cost, chain_stats) in chains {
+            if best.is_none() || cost < best_cost {
+                best = Some(tour);
+                best_cost = cost;
+            }
+            stats.push(chain_stats);
+        }
+        OptimizedOrder {
+            deliveries: problem.stops_in(best.unwrap().order()),
+            optimal: false,
+            stats,
+        }
+    }
+}
+
+impl SimulatedAnnealing {
+    pub fn new(config: OptimizerConfig) -> Self {
+        SimulatedAnnealing { config }
+    }
+    // * Unseeded, one chain per core.  Seeded, a fixed number, so plans don't depend on the machine.
+    fn thread_count(&self) -> usize {
+        match (self.config.threads, self.config.seed) {
+            (Some(threads), _) => threads.max(1),
+            (None, Some(_)) => SEEDED_THREADS,
+            (None, None) => thread::available_parallelism().map_or(1, |threads| threads.get()),
+        }
+    }
+    // Whether a worse tour, delta miles (and penalties) worse, is taken anyway.
+    fn accepts(&self, delta: f64, temperature: f64, rng: &fastrand::Rng) -> bool {
+        match self.config.acceptance {
+            Acceptance::Threshold => (rng.f32() as f64) < temperature,
+            Acceptance::Metropolis => rng.f64() < (-delta / temperature).exp(),
+        }
+    }
+    fn out_of_budget(&self, stats: &ChainStats, deadline: Option<Instant>) -> bool {
+        self.config.max_moves.is_some_and(|max_moves| stats.moves >= max_moves)
+            || deadline.is_some_and(|deadline| {
+                stats.moves.is_multiple_of(CLOCK_CHECK_MOVES) && Instant::now() >= deadline
+            })
+    }
+    // * One chain: anneal from the given order, then keep annealing again from the best tour so far.
+    fn chain(&self, problem: &TourProblem, thread: usize) -> (Tour, f64, ChainStats) {
+        let started = Instant::now();
+        let deadline = self.config.time_limit.map(|limit| started + limit);
+        let rng = fastrand::Rng::new();
+        if let Some(seed) = self.config.seed {
+            rng.seed(seed.wrapping_add(thread as u64));
+        }
+        let mut stats = ChainStats {
+            thread,
+            ..ChainStats::default()
+        };
+        let order: Vec<usize> = (1..=problem.stop_count()).collect();
+        let tour = Tour::new(&order, problem.costs());
+        let cost = problem.tour_cost(&tour);
+        let (mut best_tour, mut best_cost) =
+            self.iterate(problem, tour, cost, &rng, deadline, &mut stats);
+        for _ in 0..self.config.restarts {
+            if self.out_of_budget(&stats, deadline) {
+                break;
+            }
+            let (new_tour, new_cost) =
+                self.iterate(problem, best_tour.clone(), best_cost, &rng, deadline, &mut stats);
+            if new_cost < best_cost {
+                best_tour = new_tour;
+                best_cost = new_cost;
+            }
+        }
+        stats.best_cost = best_cost;
+        stats.elapsed = started.elapsed();
+        (best_tour, best_cost, stats)
+    }
+    fn iterate(
+        &self,
+        problem: &TourProblem,
+        tour: Tour,
+        cost: f64,
+        rng: &fastrand::Rng,
+        deadline: Option<Instant>,
+        stats: &mut ChainStats,
+    ) -> (Tour, f64) {
+        let mut no_improvements = 0;
+        let size = tour.order().len();
+
+        let mut current_tour = tour;
+        let mut current_cost = cost;
+        let mut best_tour = current_tour.clone();
+        let mut best_cost = current_cost;
+
+        let limit = self.config.patience.unwrap_or(match size {
+            0..=10 => 50,
+            11..=100 => 1000,
+            _ => 2000,
+        });
+        let mut temperature = self.config.initial_temperature;
+        // Where reheating goes back to: the temperature the best tour was found at.
+        let mut best_temperature = temperature;
+
+        while no_improvements < limit && !self.out_of_budget(stats, deadline) {
+            let Some((mv, new_cost)) = self.permute(problem, &current_tour, current_cost, rng) else {
+                // * Tours of fewer than two stops have no moves at all.  Otherwise this try came up
+                // * empty, and counts against patience like any other move that didn't help.
+                if size < 2 {
+                    break;
+                }
+                stats.missed += 1;
+                no_improvements += 1;
+                continue;
+            };
+            stats.moves += 1;
+            if new_cost < current_cost {
+                current_tour.apply(&mv, problem.costs());
+                current_cost = new_cost;
+                stats.accepted += 1;
+                if new_cost < best_cost {
+                    best_cost = new_cost;
+                    best_tour = current_tour.clone();
+                    best_temperature = temperature;
+                    no_improvements = 0;
+                }
+            } else {
+                if self.accepts(new_cost - current_cost, temperature, rng) {
+                    current_tour.apply(&mv, problem.costs());
+                    current_cost = new_cost;
+                    stats.accepted += 1;
+                    stats.uphill += 1;
+                }
+                no_improvements += 1;
+                temperature = match self.config.cooling {
+                    Cooling::Geometric { factor } => temperature * factor,
+                    Cooling::Linear { step } => (temperature - step).max(0.),
+                    Cooling::Reheating { factor, stall } => {
+                        if no_improvements.is_multiple_of(stall) {
+                            best_temperature
+                        } else {
+                            temperature * factor
+                        }
+                    }
+                };
+            }
+        }
+        (best_tour, best_cost)
+    }
+    // * A random 2-opt, Or-opt or 3-opt move and what the tour would cost after it.  With only
+    // * distance to go on, that comes from the move's delta; otherwise the new tour is scored in
+    // * full, and moves that would drop an item off before its pickup are passed over.
+    fn permute(
+        &self,
+        problem: &TourProblem,
+        tour: &Tour,
+        cost: f64,
+        rng: &fastrand::Rng,
+    ) -> Option<(Move, f64)> {
+        if !problem.is_constrained() {
+            let mv = tour.random_move(rng)?;
+            return Some((mv, cost + tour.delta(&mv, problem.costs())));
+        }
+        for _ in 0..PERMUTE_ATTEMPTS {
+            let mv = tour.random_move(rng)?;
+            let new_cost = problem.cost(&tour.order_after(&mv));
+            if new_cost.is_finite() {
+                return Some((mv, new_cost));
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::optimizer::DeliveryOptimizer;
+    use crate::provided::{DeliveryRequest, GeoCoord, StopKind};
+
+    #[test]
+    fn chains_count_moves_they_cant_make() {
+        // * With just a pickup and its drop-off, every move would swap them, so none can be made.
+        let dropoff = DeliveryRequest {
+            pickup: Some(GeoCoord::from_degrees(34.06, -118.43)),
+            ..DeliveryRequest::from("Tacos", &GeoCoord::from_degrees(34.06, -118.42))
+        };
+        let deliveries = vec![dropoff.pickup_stop().unwrap(), dropoff];
+        let annealing = SimulatedAnnealing::new(OptimizerConfig {
+            patience: Some(5),
+            restarts: 2,
+            threads: Some(1),
+            seed: Some(1),
+            exact_threshold: 0,
+            ..OptimizerConfig::default()
+        });
+        let depot = GeoCoord::from_degrees(34.06, -118.44);
+        let order = DeliveryOptimizer::new(&annealing).optimize_order(&depot, deliveries);
+        let kinds: Vec<_> = order.deliveries.iter().map(|stop| stop.kind).collect();
+        assert_eq!(kinds, [StopKind::Pickup, StopKind::Dropoff]);
+        // * Each of the three runs gives up once patience runs out, rather than on the first miss.
+        let stats = &order.stats[0];
+        assert_eq!((stats.moves, stats.missed), (0, 15));
+    }
+
+    // Stops strung out east of the depot, zigzagging north and south.
+    fn scattered(count: usize) -> Vec<DeliveryRequest> {
+        (0..count)
+            .map(|i| {
+                let (lat, lon) = (34.06 + (i * 7 % 5) as f64 * 0.001, -118.44 + i as f64 * 0.001);
+                DeliveryRequest::from(&format!("Box {}", i), &GeoCoord::from_degrees(lat, lon))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn exact_threshold_is_capped() {
+        // * Solved exactly, 30 stops would need far more memory than there is; annealed, they
+        // * come back in some order.
+        let deliveries = scattered(30);
+        let annealing = SimulatedAnnealing::new(OptimizerConfig {
+            max_moves: Some(1000),
+            threads: Some(1),
+            seed: Some(1),
+            exact_threshold: 40,
+            ..OptimizerConfig::default()
+        });
+        let depot = GeoCoord::from_degrees(34.06, -118.44);
+        let order = DeliveryOptimizer::new(&annealing).optimize_order(&depot, deliveries);
+        assert_eq!(order.deliveries.len(), 30);
+        assert!(!order.optimal);
+    }
+
+    #[test]
+    fn seeded_runs_repeat() {
+        let depot = GeoCoord::from_degrees(34.06, -118.44);
+        let run = |threads: usize| {
+            let annealing = SimulatedAnnealing::new(OptimizerConfig {
+                max_moves: Some(2000),
+                threads: Some(threads),
+                seed: Some(42),
+                ..OptimizerConfig::default()
+            });
+            let order = DeliveryOptimizer::new(&annealing).optimize_order(&depot, scattered(25));
+            order.deliveries.into_iter().map(|stop| stop.item).collect::<Vec<_>>()
+        };
+        // * However the chains' threads happen to be scheduled, the same chains win.
+        for threads in [1, 4] {
+            assert_eq!(run(threads), run(threads), "{} threads", threads);
+        }
+    }
+}
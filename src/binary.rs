@@ -0,0 +1,79 @@
+use crate::provided::GeoCoord;
+use std::io::{self, Read, Write};
+
+// Little-endian helpers shared by the files the program caches on disk.
+
+pub fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+pub fn write_u32(w: &mut impl Write, value: u32) -> io::Result<()> {
+    w.write_all(&value.to_le_bytes())
+}
+
+pub fn write_u64(w: &mut impl Write, value: u64) -> io::Result<()> {
+    w.write_all(&value.to_le_bytes())
+}
+
+pub fn write_f64(w: &mut impl Write, value: f64) -> io::Result<()> {
+    w.write_all(&value.to_le_bytes())
+}
+
+pub fn write_str(w: &mut impl Write, value: &str) -> io::Result<()> {
+    write_u32(w, value.len() as u32)?;
+    w.write_all(value.as_bytes())
+}
+
+pub fn read_u32(r: &mut impl Read) -> io::Result<u32> {
+    let mut bytes = [0u8; 4];
+    r.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+pub fn read_u64(r: &mut impl Read) -> io::Result<u64> {
+    let mut bytes = [0u8; 8];
+    r.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+pub fn read_f64(r: &mut impl Read) -> io::Result<f64> {
+    read_u64(r).map(f64::from_bits)
+}
+
+// * Reads how many of something follow, each taking at least bytes_each bytes.  A count the
+// * rest of the file can't hold is refused before anything is allocated for it.
+pub fn read_count(r: &mut &[u8], bytes_each: usize) -> io::Result<usize> {
+    let count = read_u32(r)? as usize;
+    check_fits(r, count, bytes_each)?;
+    Ok(count)
+}
+
+pub fn check_fits(r: &[u8], count: usize, bytes_each: usize) -> io::Result<()> {
+    if count.saturating_mul(bytes_each) > r.len() {
+        return Err(invalid("count runs past the end of the file"));
+    }
+    Ok(())
+}
+
+// * Reads the text through take(), which grows the buffer only as bytes arrive, so a damaged
+// * length can't ask for more memory than the file holds.
+pub fn read_str(r: &mut impl Read) -> io::Result<String> {
+    let len = read_u32(r)? as u64;
+    let mut bytes = Vec::new();
+    r.take(len).read_to_end(&mut bytes)?;
+    if bytes.len() as u64 != len {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    String::from_utf8(bytes).map_err(|_| invalid("text is not UTF-8"))
+}
+
+// * A coordinate as its latitude and longitude text, checked to be numbers since
+// * GeoCoord::from panics on anything else.
+pub fn read_coord(r: &mut impl Read) -> io::Result<GeoCoord> {
+    let lat = read_str(r)?;
+    let lon = read_str(r)?;
+    if lat.trim().parse::<f64>().is_err() || lon.trim().parse::<f64>().is_err() {
+        return Err(invalid("coordinate is not a number"));
+    }
+    Ok(GeoCoord::from(&lat, &lon))
+}
@@ -0,0 +1,146 @@
+use crate::optimizer::{OptimizedOrder, TourOptimizer, TourProblem};
+use crate::tour::DEPOT;
+
+// * Always drives to the closest stop not yet visited.  Quick, but often a good deal longer than it
+// * needs to be, since the last few stops are wherever the rest left them.
+pub struct NearestNeighbour;
+
+impl TourOptimizer for NearestNeighbour {
+    fn optimize(&self, problem: &TourProblem) -> OptimizedOrder {
+        OptimizedOrder::heuristic(problem, &nearest_neighbour_order(problem))
+    }
+}
+
+// Drop-offs only become candidates once their pickup is done.
+pub fn nearest_neighbour_order(problem: &TourProblem) -> Vec<usize> {
+    let before = problem.pickup_nodes();
+    let costs = problem.costs();
+    let stops = problem.stop_count();
+    let mut visited = vec![false; stops + 1];
+    let mut order = Vec::with_capacity(stops);
+    let mut current = DEPOT;
+    for _ in 0..stops {
+        let next = (1..=stops)
+            .filter(|&node| !visited[node] && before[node].is_none_or(|first| visited[first]))
+            .min_by(|&a, &b| costs.get(current, a).total_cmp(&costs.get(current, b)))
+            .unwrap();
+        visited[next] = true;
+        order.push(next);
+        current = next;
+    }
+    order
+}
+
+// * Christofides' construction: a minimum spanning tree over the stops, plus a matching between
+// * its odd-degree stops, walked as an Euler tour that skips stops it has already been to.
+// * The real thing needs a minimum-weight perfect matching and the same cost both ways; this
+// * matches the closest pairs first and averages the two directions of each leg, so its
+// * "at most half again as long as the best" guarantee doesn't carry over.
+pub struct Christofides;
+
+impl TourOptimizer for Christofides {
+    fn optimize(&self, problem: &TourProblem) -> OptimizedOrder {
+        let mut forward = christofides_order(problem);
+        let mut backward: Vec<usize> = forward.iter().rev().copied().collect();
+        // * With one-way streets, the walk may be shorter one way round than the other.
+        put_pickups_first(problem, &mut forward);
+        put_pickups_first(problem, &mut backward);
+        let order = if problem.cost(&backward) < problem.cost(&forward) {
+            backward
+        } else {
+            forward
+        };
+        OptimizedOrder::heuristic(problem, &order)
+    }
+}
+
+fn christofides_order(problem: &TourProblem) -> Vec<usize> {
+    let costs = problem.costs();
+    let size = problem.stop_count() + 1;
+    let weight = |a: usize, b: usize| (costs.get(a, b) + costs.get(b, a)) / 2.;
+    // Multigraph of tree and matching edges, as adjacency lists.
+    let mut links: Vec<Vec<usize>> = vec![Vec::new(); size];
+
+    // Prim's algorithm, growing the tree out from the depot.
+    let mut in_tree = vec![false; size];
+    let mut distance = vec![f64::INFINITY; size];
+    let mut parent = vec![DEPOT; size];
+    distance[DEPOT] = 0.;
+    for _ in 0..size {
+        let node = (0..size)
+            .filter(|&node| !in_tree[node])
+            .min_by(|&a, &b| distance[a].total_cmp(&distance[b]))
+            .unwrap();
+        in_tree[node] = true;
+        if node != DEPOT {
+            links[node].push(parent[node]);
+            links[parent[node]].push(node);
+        }
+        for other in 0..size {
+            if !in_tree[other] && weight(node, other) < distance[other] {
+                distance[other] = weight(node, other);
+                parent[other] = node;
+            }
+        }
+    }
+
+    // Greedy matching of the odd-degree nodes, closest pairs first.
+    let odd: Vec<usize> = (0..size).filter(|&node| links[node].len() % 2 == 1).collect();
+    let mut pairs: Vec<(usize, usize)> = Vec::new();
+    for (i, &a) in odd.iter().enumerate() {
+        for &b in &odd[i + 1..] {
+            pairs.push((a, b));
+        }
+    }
+    pairs.sort_by(|&(a, b), &(c, d)| weight(a, b).total_cmp(&weight(c, d)));
+    let mut matched = vec![false; size];
+    for (a, b) in pairs {
+        if !matched[a] && !matched[b] {
+            matched[a] = true;
+            matched[b] = true;
+            links[a].push(b);
+            links[b].push(a);
+        }
+    }
+
+    // Hierholzer's algorithm for the Euler tour, using up each edge as it goes.
+    let mut stack = vec![DEPOT];
+    let mut circuit = Vec::new();
+    while let Some(&node) = stack.last() {
+        match links[node].pop() {
+            Some(next) => {
+                let back = links[next].iter().position(|&other| other == node).unwrap();
+                links[next].swap_remove(back);
+                stack.push(next);
+            }
+            None => {
+                circuit.push(node);
+                stack.pop();
+            }
+        }
+    }
+
+    let mut seen = vec![false; size];
+    seen[DEPOT] = true;
+    circuit
+        .into_iter()
+        .filter(|&node| !std::mem::replace(&mut seen[node], true))
+        .collect()
+}
+
+// Moves each pickup that comes after its drop-off to just before it.
+fn put_pickups_first(problem: &TourProblem, order: &mut Vec<usize>) {
+    let before = problem.pickup_nodes();
+    let mut i = 0;
+    while i < order.len() {
+        if let Some(pickup) = before[order[i]] {
+            let at = order.iter().position(|&node| node == pickup).unwrap();
+            if at > i {
+                let pickup = order.remove(at);
+                order.insert(i, pickup);
+                i += 1;
+            }
+        }
+        i += 1;
+    }
+}
@@ -0,0 +1,537 @@
+use crate::binary::{check_fits, invalid, read_coord, read_count, read_f64, read_str};
+use crate::binary::{read_u32, read_u64};
+use crate::binary::{write_f64, write_str, write_u32, write_u64};
+use crate::error::{DeliveryFailure, RouteError};
+use crate::provided::{GeoCoord, StreetSegment};
+use crate::street_map::StreetMap;
+use ordered_float::OrderedFloat;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BinaryHeap, HashMap};
+use std::fs::File;
+use std::hash::{Hash, Hasher};
+use std::io::{self, BufWriter, Read, Write};
+
+const MAGIC: &[u8; 4] = b"GECH";
+const FORMAT_VERSION: u32 = 1;
+// Witness searches give up after settling this many nodes.  A missed witness only costs an extra shortcut.
+const WITNESS_SETTLE_LIMIT: usize = 64;
+
+// What an edge stands for: a piece of a named street, or two edges through a contracted node.
+#[derive(Clone, Copy, Debug)]
+enum Via {
+    Street(u32),
+    Shortcut(u32),
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Edge {
+    to: u32,
+    weight: f64,
+    via: Via,
+}
+
+// Adjacency used while contracting.  BTreeMaps keep the build order, and so the output, deterministic.
+type Adjacency = Vec<BTreeMap<u32, (f64, Via)>>;
+type SearchHeap = BinaryHeap<Reverse<(OrderedFloat<f64>, u32)>>;
+
+// * Contraction Hierarchies: nodes are ranked, and shortcuts added so that every shortest path
+// * climbs to a single highest node and then descends.  Queries only ever search upwards from
+// * both ends, which touches a few hundred nodes instead of the whole map.
+pub struct ContractionHierarchy {
+    coords: Vec<GeoCoord>,
+    index: HashMap<GeoCoord, u32>,
+    names: Vec<String>,
+    // Edges leading to higher ranked nodes, for the forward search.
+    up: Vec<Vec<Edge>>,
+    // Edges arriving from higher ranked nodes, for the backward search.  Here `to` is the tail.
+    down: Vec<Vec<Edge>>,
+    fingerprint: u64,
+}
+
+impl ContractionHierarchy {
+    pub fn build(sm: &StreetMap) -> ContractionHierarchy {
+        let mut coords: Vec<GeoCoord> = sm.coords().cloned().collect();
+        coords.sort();
+        let index = index_coords(&coords);
+        let n = coords.len();
+
+        let mut names: Vec<String> = Vec::new();
+        let mut name_ids: HashMap<String, u32> = HashMap::new();
+        let mut out: Adjacency = vec![BTreeMap::new(); n];
+        let mut inc: Adjacency = vec![BTreeMap::new(); n];
+        for (u, coord) in coords.iter().enumerate() {
+            for seg in sm.get_segments_from(coord).unwrap() {
+                let v = index[&seg.end] as usize;
+                if v == u {
+                    continue;
+                }
+                let name = *name_ids.entry(seg.name.clone()).or_insert_with(|| {
+                    names.push(seg.name.clone());
+                    names.len() as u32 - 1
+                });
+                let arc = (seg.length(), Via::Street(name));
+                insert_shorter(&mut out[u], v as u32, arc);
+                insert_shorter(&mut inc[v], u as u32, arc);
+            }
+        }
+
+        let mut deleted_neighbours = vec![0i64; n];
+        let mut queue: BinaryHeap<Reverse<(i64, u32)>> = (0..n)
+            .map(|v| {
+                let shortcuts = shortcuts_for(&out, &inc, v);
+                Reverse((priority(&out, &inc, &deleted_neighbours, v, shortcuts.len()), v as u32))
+            })
+            .collect();
+        let mut up = vec![Vec::new(); n];
+        let mut down = vec![Vec::new(); n];
+
+        // * Lazy updates: a node's priority is recomputed when it surfaces, and it goes back if it got worse.
+        while let Some(Reverse((_, v))) = queue.pop() {
+            let v = v as usize;
+            let shortcuts = shortcuts_for(&out, &inc, v);
+            let current = priority(&out, &inc, &deleted_neighbours, v, shortcuts.len());
+            if let Some(Reverse((next, _))) = queue.peek() {
+                if current > *next {
+                    queue.push(Reverse((current, v as u32)));
+                    continue;
+                }
+            }
+
+            // Every neighbour still in the graph outranks v, so v's edges are final.
+            for (&x, &(weight, via)) in &out[v] {
+                up[v].push(Edge { to: x, weight, via });
+                inc[x as usize].remove(&(v as u32));
+                deleted_neighbours[x as usize] += 1;
+            }
+            for (&u, &(weight, via)) in &inc[v] {
+                down[v].push(Edge { to: u, weight, via });
+                out[u as usize].remove(&(v as u32));
+                deleted_neighbours[u as usize] += 1;
+            }
+            out[v].clear();
+            inc[v].clear();
+
+            for (u, x, weight) in shortcuts {
+                let arc = (weight, Via::Shortcut(v as u32));
+                insert_shorter(&mut out[u as usize], x, arc);
+                insert_shorter(&mut inc[x as usize], u, arc);
+            }
+        }
+
+        ContractionHierarchy {
+            fingerprint: fingerprint(sm, &coords),
+            coords,
+            index,
+            names,
+            up,
+            down,
+        }
+    }
+    pub fn query(
+        &self,
+        start: &GeoCoord,
+        end: &GeoCoord,
+    ) -> Result<(Vec<StreetSegment>, f64), RouteError> {
+        self.query_seeded(&[(start, 0.)], &[(end, 0.)])
+            .map(|(_, _, route, distance)| (route, distance))
+    }
+    // * Searches from several starting nodes to several ending nodes at once.  Each comes with
+    // * a head start (or extra distance at the end), e.g. the partial segment from a mid-block stop.
+    // * Returns which source and target were used along with the route between them.
+    pub fn query_seeded(
+        &self,
+        sources: &[(&GeoCoord, f64)],
+        targets: &[(&GeoCoord, f64)],
+    ) -> Result<(usize, usize, Vec<StreetSegment>, f64), RouteError> {
+        let lookup = |gc: &GeoCoord| {
+            self.index
+                .get(gc)
+                .copied()
+                .ok_or_else(|| RouteError::new(DeliveryFailure::BadCoord))
+        };
+        let sources = sources
+            .iter()
+            .map(|&(gc, d)| Ok((lookup(gc)?, d)))
+            .collect::<Result<Vec<(u32, f64)>, RouteError>>()?;
+        let targets = targets
+            .iter()
+            .map(|&(gc, d)| Ok((lookup(gc)?, d)))
+            .collect::<Result<Vec<(u32, f64)>, RouteError>>()?;
+
+        let mut forward = Search::seeded(&sources);
+        let mut backward = Search::seeded(&targets);
+        let mut best = f64::INFINITY;
+        let mut meeting: Option<u32> = None;
+
+        loop {
+            let forward_min = forward.min_key();
+            let backward_min = backward.min_key();
+            if forward_min.min(backward_min) >= best {
+                break;
+            }
+            let (search, other, edges) = if forward_min <= backward_min {
+                (&mut forward, &backward, &self.up)
+            } else {
+                (&mut backward, &forward, &self.down)
+            };
+            let (node, dist) = match search.settle_next() {
+                Some(settled) => settled,
+                None => continue,
+            };
+            if let Some(&other_dist) = other.dist.get(&node) {
+                if dist + other_dist < best {
+                    best = dist + other_dist;
+                    meeting = Some(node);
+                }
+            }
+            for edge in &edges[node as usize] {
+                search.relax(node, edge);
+            }
+        }
+
+        let meeting = meeting.ok_or_else(|| RouteError::new(DeliveryFailure::NoRoute))?;
+
+        // Walk back down each half to the seed it grew from, unpacking shortcuts along the way.
+        let mut route = Vec::new();
+        let mut node = meeting;
+        let mut first_half = Vec::new();
+        while let Some(&(prev, edge)) = forward.parents.get(&node) {
+            first_half.push((prev, edge));
+            node = prev;
+        }
+        let source = node;
+        for (prev, edge) in first_half.into_iter().rev() {
+            self.unpack(prev, &edge, &mut route);
+        }
+        let mut node = meeting;
+        while let Some(&(next, edge)) = backward.parents.get(&node) {
+            // Backward edges point at their tail, so the real edge runs node -> next.
+            self.unpack(node, &Edge { to: next, ..edge }, &mut route);
+            node = next;
+        }
+        let target = node;
+
+        let source_index = seed_index(&sources, source, &forward.dist);
+        let target_index = seed_index(&targets, target, &backward.dist);
+        Ok((source_index, target_index, route, best))
+    }
+    pub fn save(&self, path: &str) -> io::Result<()> {
+        let mut w = BufWriter::new(File::create(path)?);
+        w.write_all(MAGIC)?;
+        write_u32(&mut w, FORMAT_VERSION)?;
+        write_u64(&mut w, self.fingerprint)?;
+        write_u32(&mut w, self.coords.len() as u32)?;
+        for coord in &self.coords {
+            write_str(&mut w, coord.lat_text())?;
+            write_str(&mut w, coord.lon_text())?;
+        }
+        write_u32(&mut w, self.names.len() as u32)?;
+        for name in &self.names {
+            write_str(&mut w, name)?;
+        }
+        for edges in self.up.iter().chain(self.down.iter()) {
+            write_u32(&mut w, edges.len() as u32)?;
+            for edge in edges {
+                write_u32(&mut w, edge.to)?;
+                write_f64(&mut w, edge.weight)?;
+                let (tag, value) = match edge.via {
+                    Via::Street(name) => (0u8, name),
+                    Via::Shortcut(mid) => (1u8, mid),
+                };
+                w.write_all(&[tag])?;
+                write_u32(&mut w, value)?;
+            }
+        }
+        w.flush()
+    }
+    // * Refuses files written for a different map, since their node numbering would be meaningless.
+    pub fn load(path: &str, sm: &StreetMap) -> io::Result<ContractionHierarchy> {
+        let data = std::fs::read(path)?;
+        let mut r: &[u8] = &data;
+        let mut magic = [0u8; 4];
+        r.read_exact(&mut magic)?;
+        if &magic != MAGIC || read_u32(&mut r)? != FORMAT_VERSION {
+            return Err(invalid("not a contraction hierarchy file of this version"));
+        }
+        let stored_fingerprint = read_u64(&mut r)?;
+
+        // Coordinates are two strings, each at least a length.
+        let node_count = read_count(&mut r, 8)?;
+        let mut coords = Vec::with_capacity(node_count);
+        for _ in 0..node_count {
+            coords.push(read_coord(&mut r)?);
+        }
+        if fingerprint(sm, &coords) != stored_fingerprint {
+            return Err(invalid("contraction hierarchy was built from a different map"));
+        }
+        let name_count = read_count(&mut r, 4)?;
+        let mut names = Vec::with_capacity(name_count);
+        for _ in 0..name_count {
+            names.push(read_str(&mut r)?);
+        }
+        check_fits(r, 2 * node_count, 4)?;
+        let mut lists = Vec::with_capacity(2 * node_count);
+        for _ in 0..2 * node_count {
+            // Edges are a node, a weight, a tag and a value.
+            let edge_count = read_count(&mut r, 17)?;
+            let mut edges = Vec::with_capacity(edge_count);
+            for _ in 0..edge_count {
+                let to = read_u32(&mut r)?;
+                let weight = read_f64(&mut r)?;
+                let mut tag = [0u8; 1];
+                r.read_exact(&mut tag)?;
+                let value = read_u32(&mut r)?;
+                let via = match tag[0] {
+                    0 => Via::Street(value),
+                    1 => Via::Shortcut(value),
+                    _ => return Err(invalid("unknown edge kind")),
+                };
+                if to as usize >= node_count {
+                    return Err(invalid("edge leads to a missing node"));
+                }
+                edges.push(Edge { to, weight, via });
+            }
+            lists.push(edges);
+        }
+        if !r.is_empty() {
+            return Err(invalid("contraction hierarchy has trailing data"));
+        }
+        let down = lists.split_off(node_count);
+        check_shortcuts(&lists, &down, name_count)?;
+
+        Ok(ContractionHierarchy {
+            index: index_coords(&coords),
+            coords,
+            names,
+            up: lists,
+            down,
+            fingerprint: stored_fingerprint,
+        })
+    }
+    fn unpack(&self, from: u32, edge: &Edge, route: &mut Vec<StreetSegment>) {
+        match edge.via {
+            Via::Street(name) => route.push(StreetSegment::from(
+                &self.coords[from as usize],
+                &self.coords[edge.to as usize],
+                &self.names[name as usize],
+            )),
+            Via::Shortcut(mid) => {
+                // mid was contracted before both ends, so both halves are stored on mid itself.
+                let first = self.down[mid as usize]
+                    .iter()
+                    .find(|e| e.to == from)
+                    .expect("Shortcut without its first half.");
+                let second = self.up[mid as usize]
+                    .iter()
+                    .find(|e| e.to == edge.to)
+                    .expect("Shortcut without its second half.");
+                self.unpack(from, &Edge { to: mid, ..*first }, route);
+                self.unpack(mid, second, route);
+            }
+        }
+    }
+}
+
+// One direction of the bidirectional query.
+struct Search {
+    heap: SearchHeap,
+    dist: HashMap<u32, f64>,
+    parents: HashMap<u32, (u32, Edge)>,
+}
+
+impl Search {
+    fn seeded(seeds: &[(u32, f64)]) -> Search {
+        let mut search = Search {
+            heap: BinaryHeap::new(),
+            dist: HashMap::new(),
+            parents: HashMap::new(),
+        };
+        for &(node, d) in seeds {
+            if search.dist.get(&node).is_none_or(|&old| d < old) {
+                search.dist.insert(node, d);
+                search.heap.push(Reverse((OrderedFloat(d), node)));
+            }
+        }
+        search
+    }
+    fn min_key(&self) -> f64 {
+        self.heap
+            .peek()
+            .map_or(f64::INFINITY, |Reverse((d, _))| d.into_inner())
+    }
+    // Pops the closest node, skipping stale heap entries.
+    fn settle_next(&mut self) -> Option<(u32, f64)> {
+        let Reverse((d, node)) = self.heap.pop()?;
+        if d.into_inner() > self.dist[&node] {
+            return None;
+        }
+        Some((node, d.into_inner()))
+    }
+    fn relax(&mut self, from: u32, edge: &Edge) {
+        let d = self.dist[&from] + edge.weight;
+        if self.dist.get(&edge.to).is_none_or(|&old| d < old) {
+            self.dist.insert(edge.to, d);
+            self.parents.insert(edge.to, (from, *edge));
+            self.heap.push(Reverse((OrderedFloat(d), edge.to)));
+        }
+    }
+}
+
+// * What unpack relies on, checked once when a file is loaded: street names exist, every
+// * shortcut's two halves are stored on its middle node, and no chain of edges leads back to
+// * where it started, so unpacking a shortcut always reaches streets.  An edge from a to b is
+// * stored as up[a] with `to` b, or as down[b] with `to` a.
+fn check_shortcuts(up: &[Vec<Edge>], down: &[Vec<Edge>], name_count: usize) -> io::Result<()> {
+    let n = up.len();
+    let stored = |edges: &[Edge], to: u32| edges.iter().any(|edge| edge.to == to);
+    let check = |a: u32, b: u32, via: Via| match via {
+        Via::Street(name) => (name as usize) < name_count,
+        Via::Shortcut(mid) => {
+            (mid as usize) < n && stored(&down[mid as usize], a) && stored(&up[mid as usize], b)
+        }
+    };
+    // Both lists point from a node to higher ranked ones, which must leave no cycles.
+    let mut lower = vec![0usize; n];
+    for node in 0..n {
+        for edge in &up[node] {
+            if !check(node as u32, edge.to, edge.via) {
+                return Err(invalid("edge refers to a missing name or shortcut half"));
+            }
+            lower[edge.to as usize] += 1;
+        }
+        for edge in &down[node] {
+            if !check(edge.to, node as u32, edge.via) {
+                return Err(invalid("edge refers to a missing name or shortcut half"));
+            }
+            lower[edge.to as usize] += 1;
+        }
+    }
+    let mut ready: Vec<usize> = (0..n).filter(|&node| lower[node] == 0).collect();
+    let mut ranked = 0;
+    while let Some(node) = ready.pop() {
+        ranked += 1;
+        for edge in up[node].iter().chain(&down[node]) {
+            lower[edge.to as usize] -= 1;
+            if lower[edge.to as usize] == 0 {
+                ready.push(edge.to as usize);
+            }
+        }
+    }
+    if ranked < n {
+        return Err(invalid("contraction hierarchy edges form a cycle"));
+    }
+    Ok(())
+}
+
+fn index_coords(coords: &[GeoCoord]) -> HashMap<GeoCoord, u32> {
+    coords
+        .iter()
+        .enumerate()
+        .map(|(i, gc)| (gc.clone(), i as u32))
+        .collect()
+}
+
+fn insert_shorter(edges: &mut BTreeMap<u32, (f64, Via)>, to: u32, arc: (f64, Via)) {
+    if edges.get(&to).is_none_or(|&(old, _)| arc.0 < old) {
+        edges.insert(to, arc);
+    }
+}
+
+// Edge difference plus contracted neighbours, which spreads contraction evenly over the map.
+fn priority(out: &Adjacency, inc: &Adjacency, deleted: &[i64], v: usize, shortcuts: usize) -> i64 {
+    shortcuts as i64 - (out[v].len() + inc[v].len()) as i64 + deleted[v]
+}
+
+// The shortcuts (u, x, weight) needed to keep distances exact once v is removed.
+fn shortcuts_for(out: &Adjacency, inc: &Adjacency, v: usize) -> Vec<(u32, u32, f64)> {
+    let mut shortcuts = Vec::new();
+    for (&u, &(w_uv, _)) in &inc[v] {
+        let limit = out[v]
+            .iter()
+            .filter(|(&x, _)| x != u)
+            .map(|(_, &(w_vx, _))| w_uv + w_vx)
+            .fold(0., f64::max);
+        let witnesses = witness_search(out, u, v as u32, limit);
+        for (&x, &(w_vx, _)) in &out[v] {
+            if x == u {
+                continue;
+            }
+            let through_v = w_uv + w_vx;
+            if witnesses.get(&x).is_none_or(|&d| d > through_v) {
+                shortcuts.push((u, x, through_v));
+            }
+        }
+    }
+    shortcuts
+}
+
+// A small Dijkstra from u that avoids v, looking for paths at least as short as going through v.
+fn witness_search(out: &Adjacency, u: u32, v: u32, limit: f64) -> HashMap<u32, f64> {
+    let mut dist: HashMap<u32, f64> = HashMap::new();
+    let mut heap: SearchHeap = BinaryHeap::new();
+    dist.insert(u, 0.);
+    heap.push(Reverse((OrderedFloat(0.), u)));
+    let mut settled = 0;
+    while let Some(Reverse((d, node))) = heap.pop() {
+        let d = d.into_inner();
+        if d > dist[&node] {
+            continue;
+        }
+        if d > limit || settled >= WITNESS_SETTLE_LIMIT {
+            break;
+        }
+        settled += 1;
+        for (&next, &(w, _)) in &out[node as usize] {
+            if next == v {
+                continue;
+            }
+            let nd = d + w;
+            if dist.get(&next).is_none_or(|&old| nd < old) {
+                dist.insert(next, nd);
+                heap.push(Reverse((OrderedFloat(nd), next)));
+            }
+        }
+    }
+    dist
+}
+
+// Which seed a search half ended on.  Seeds can share a node, so take the one that set its distance.
+fn seed_index(seeds: &[(u32, f64)], node: u32, dist: &HashMap<u32, f64>) -> usize {
+    seeds
+        .iter()
+        .position(|&(n, d)| n == node && d == dist[&node])
+        .unwrap_or(0)
+}
+
+// * FNV-1a over every node and segment, used to tell whether a saved hierarchy matches a map.
+struct Fnv(u64);
+
+impl Hasher for Fnv {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= *byte as u64;
+            self.0 = self.0.wrapping_mul(0x100_0000_01b3);
+        }
+    }
+}
+
+fn fingerprint(sm: &StreetMap, coords: &[GeoCoord]) -> u64 {
+    let mut hasher = Fnv(0xcbf2_9ce4_8422_2325);
+    coords.len().hash(&mut hasher);
+    for coord in coords {
+        coord.hash(&mut hasher);
+        match sm.get_segments_from(coord) {
+            Some(segs) => {
+                for seg in segs {
+                    seg.end.hash(&mut hasher);
+                    seg.name.hash(&mut hasher);
+                }
+            }
+            None => u64::MAX.hash(&mut hasher),
+        }
+    }
+    hasher.finish()
+}
@@ -0,0 +1,43 @@
+use crate::point_router::PointToPointRouter;
+use crate::provided::GeoCoord;
+use crate::schedule::Speeds;
+use crate::street_map::Snap;
+use std::collections::HashMap;
+
+// * Driving distances between every pair of stops, so the optimizer can score tours by the
+// * streets they will actually use rather than as the crow flies.
+pub struct DistanceMatrix {
+    index: HashMap<GeoCoord, usize>,
+    costs: Vec<Vec<f64>>,
+    // Minutes to drive each of those shortest routes.
+    times: Vec<Vec<f64>>,
+}
+
+impl DistanceMatrix {
+    // One Dijkstra per stop, each finding its distance to all the other stops at once.
+    pub fn compute(router: &PointToPointRouter, stops: &[Snap], speeds: &Speeds) -> DistanceMatrix {
+        let (costs, times) = stops
+            .iter()
+            .map(|stop| router.distances_from(stop, stops, speeds).into_iter().unzip())
+            .unzip();
+        DistanceMatrix {
+            index: stops
+                .iter()
+                .enumerate()
+                .map(|(i, stop)| (stop.location.clone(), i))
+                .collect(),
+            costs,
+            times,
+        }
+    }
+    // The driving distance between two stops the matrix was computed for.
+    pub fn cost(&self, from: &GeoCoord, to: &GeoCoord) -> f64 {
+        self.costs[self.index[from]][self.index[to]]
+    }
+    pub fn time(&self, from: &GeoCoord, to: &GeoCoord) -> f64 {
+        self.times[self.index[from]][self.index[to]]
+    }
+    pub fn all_reachable(&self) -> bool {
+        self.costs.iter().flatten().all(|cost| cost.is_finite())
+    }
+}
@@ -0,0 +1,117 @@
+use crate::construction::nearest_neighbour_order;
+use crate::optimizer::{OptimizedOrder, TourOptimizer, TourProblem};
+use crate::tour::{Move, Tour, DEPOT};
+
+// How many ejections a chain makes before giving up on it.
+const MAX_DEPTH: usize = 8;
+// Chains have to beat the tour they started from by more than this, so rounding can't loop forever.
+const MIN_GAIN: f64 = 1e-9;
+
+// * An ejection chain in the manner of Glover's stem-and-cycle.  Cutting a tour's last leg home
+// * leaves a path out of the depot, and its free end is the stem's tip.  An ejection links the tip
+// * to a stop on the path and drops the leg out of that stop, reversing what lies between, so the
+// * far end of the dropped leg becomes the new tip.  (The same works from the depot's other side.)
+// * Each trial tour closes the path back to the depot.
+// * A chain opens with the best 2-opt move, then keeps making the best ejection it can, even a
+// * worse one, never linking to the same stop twice.  The best trial tour along the way is kept if
+// * it beats the tour the chain started from, and chains run until none does.
+// * Starts from the nearest neighbour tour.
+pub struct StemAndCycle;
+
+impl TourOptimizer for StemAndCycle {
+    fn optimize(&self, problem: &TourProblem) -> OptimizedOrder {
+        let mut tour = Tour::new(&nearest_neighbour_order(problem), problem.costs());
+        let mut cost = problem.tour_cost(&tour);
+        while let Some((better, better_cost)) = chain(problem, &tour, cost) {
+            tour = better;
+            cost = better_cost;
+        }
+        OptimizedOrder::heuristic(problem, tour.order())
+    }
+}
+
+fn chain(problem: &TourProblem, start: &Tour, start_cost: f64) -> Option<(Tour, f64)> {
+    let stops = start.order().len();
+    if stops < 2 {
+        return None;
+    }
+    let mut tour = start.clone();
+    let mut cost = start_cost;
+    let mut best: Option<(Tour, f64)> = None;
+    // Stops the tip has been linked to.
+    let mut tabu = vec![false; stops + 1];
+
+    for depth in 0..MAX_DEPTH {
+        let candidates = if depth == 0 {
+            opening_moves(&tour)
+        } else {
+            ejections(&tour)
+        };
+        let mut choice: Option<(Move, f64, Vec<usize>)> = None;
+        for (mv, linked) in candidates {
+            if linked.iter().any(|&node| tabu[node]) {
+                continue;
+            }
+            let new_cost = cost_after(problem, &tour, cost, &mv);
+            if new_cost.is_finite() && choice.as_ref().is_none_or(|(_, c, _)| new_cost < *c) {
+                choice = Some((mv, new_cost, linked));
+            }
+        }
+        let Some((mv, new_cost, linked)) = choice else {
+            break;
+        };
+        tour.apply(&mv, problem.costs());
+        cost = new_cost;
+        for node in linked {
+            tabu[node] = true;
+        }
+        if cost < best.as_ref().map_or(start_cost, |(_, best_cost)| *best_cost) - MIN_GAIN {
+            best = Some((tour.clone(), cost));
+        }
+    }
+    best
+}
+
+fn cost_after(problem: &TourProblem, tour: &Tour, cost: f64, mv: &Move) -> f64 {
+    if problem.is_constrained() {
+        problem.cost(&tour.order_after(mv))
+    } else {
+        cost + tour.delta(mv, problem.costs())
+    }
+}
+
+// The stop at a position in the tour, counting the depot at either end.
+fn node_at(tour: &Tour, position: usize) -> usize {
+    let order = tour.order();
+    if position == 0 || position > order.len() {
+        DEPOT
+    } else {
+        order[position - 1]
+    }
+}
+
+// Every 2-opt move, with the stops that gain new legs.
+fn opening_moves(tour: &Tour) -> Vec<(Move, Vec<usize>)> {
+    let stops = tour.order().len();
+    let mut moves = Vec::new();
+    for i in 1..stops {
+        for j in i + 1..=stops {
+            let linked = vec![node_at(tour, i - 1), node_at(tour, j + 1)];
+            moves.push((Move::Reverse { i, j }, linked));
+        }
+    }
+    moves
+}
+
+// Links from the tip at the end of the tour, or the one at its start, to each stop on the path.
+fn ejections(tour: &Tour) -> Vec<(Move, Vec<usize>)> {
+    let stops = tour.order().len();
+    let mut moves = Vec::new();
+    for i in 2..stops {
+        moves.push((Move::Reverse { i, j: stops }, vec![node_at(tour, i - 1)]));
+    }
+    for j in 2..stops {
+        moves.push((Move::Reverse { i: 1, j }, vec![node_at(tour, j + 1)]));
+    }
+    moves
+}
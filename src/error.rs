@@ -1,11 +1,12 @@
 use std::fmt;
 
 #[allow(dead_code)]
-
 #[derive(Debug, Clone)]
 pub enum DeliveryFailure {
     BadCoord,
     NoRoute,
+    NoVehicles,
+    OverCapacity,
     Other,
 }
 
@@ -20,6 +21,9 @@ impl RouteError {
             kind,
         }
     }
+    pub fn kind(&self) -> &DeliveryFailure {
+        &self.kind
+    }
 }
 
 impl fmt::Display for RouteError {
@@ -31,6 +35,12 @@ impl fmt::Display for RouteError {
             DeliveryFailure::NoRoute => {
                 writeln!(f, "No route can be found to deliver all items.")
             }
+            DeliveryFailure::NoVehicles => {
+                writeln!(f, "At least one vehicle is needed to deliver items.")
+            }
+            DeliveryFailure::OverCapacity => {
+                writeln!(f, "One or more deliveries are too large for a vehicle to carry.")
+            }
             DeliveryFailure::Other => {
                 writeln!(f, "An unknown error has occured.")
             }
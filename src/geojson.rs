@@ -0,0 +1,83 @@
+use crate::planner::{DeliveryPlan, LegEnd};
+use crate::provided::{GeoCoord, StopKind, StreetSegment};
+use serde_json::{json, Value};
+
+// * The --format geojson output: a FeatureCollection any map viewer can open.  Each vehicle's legs
+// * are LineStrings, and the depot and every stop are Points.  Fleets share one depot Point.
+pub fn plan_geojson(plans: &[DeliveryPlan]) -> String {
+    let mut features = Vec::new();
+    if let Some(first) = plans.first() {
+        features.push(point(&first.depot, json!({ "kind": "depot", "label": "Depot" })));
+    }
+    for (i, plan) in plans.iter().enumerate() {
+        let vehicle = i + 1;
+        for (index, leg) in plan.legs.iter().enumerate() {
+            let (end, stop) = match leg.end {
+                LegEnd::Stop(stop) => ("stop", Some(stop)),
+                LegEnd::Reload => ("reload", None),
+                LegEnd::Finish => ("finish", None),
+            };
+            features.push(json!({
+                "type": "Feature",
+                "geometry": line_string(&leg.route),
+                "properties": {
+                    "vehicle": vehicle,
+                    "leg": index,
+                    "end": end,
+                    "stop": stop,
+                    "distance": leg.distance,
+                    "streets": street_names(&leg.route),
+                },
+            }));
+        }
+        for (index, stop) in plan.order.iter().enumerate() {
+            let kind = match stop.kind {
+                StopKind::Dropoff => "dropoff",
+                StopKind::Pickup => "pickup",
+            };
+            let properties = json!({
+                "kind": kind,
+                "label": stop.item,
+                "vehicle": vehicle,
+                "stop": index,
+            });
+            features.push(point(&stop.location, properties));
+        }
+    }
+    let collection = json!({ "type": "FeatureCollection", "features": features });
+    serde_json::to_string_pretty(&collection).expect("GeoJSON always serializes")
+}
+
+// GeoJSON positions are longitude first.
+fn position(gc: &GeoCoord) -> Value {
+    json!([gc.longitude(), gc.latitude()])
+}
+
+fn point(gc: &GeoCoord, properties: Value) -> Value {
+    json!({
+        "type": "Feature",
+        "geometry": { "type": "Point", "coordinates": position(gc) },
+        "properties": properties,
+    })
+}
+
+// * A leg that starts where it ends has no line to draw, so its geometry is null.
+fn line_string(route: &[StreetSegment]) -> Value {
+    let Some(first) = route.first() else {
+        return Value::Null;
+    };
+    let mut coordinates = vec![position(&first.start)];
+    coordinates.extend(route.iter().map(|segment| position(&segment.end)));
+    json!({ "type": "LineString", "coordinates": coordinates })
+}
+
+// Each street the leg drives along, in order, once per stretch.
+fn street_names(route: &[StreetSegment]) -> Vec<&str> {
+    let mut names: Vec<&str> = Vec::new();
+    for segment in route {
+        if names.last() != Some(&segment.name.as_str()) {
+            names.push(&segment.name);
+        }
+    }
+    names
+}
@@ -0,0 +1,107 @@
+use crate::planner::DeliveryPlan;
+use crate::provided::{DeliveryCommand, GeoCoord, StopKind, StreetSegment};
+use crate::xml::escape;
+use std::fmt::Write;
+
+// * The --format gpx output, for GPS units and phone apps: a GPX 1.1 document with a waypoint for
+// * the depot and every stop, then for each vehicle a route whose points carry its directions,
+// * and a track of every segment it drives, one track segment per leg.
+pub fn plan_gpx(plans: &[DeliveryPlan]) -> String {
+    let mut gpx = String::new();
+    gpx.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    gpx.push_str(
+        "<gpx version=\"1.1\" creator=\"goober_eats\" \
+         xmlns=\"http://www.topografix.com/GPX/1/1\">\n",
+    );
+    // * GPX wants every waypoint ahead of the routes, and the routes ahead of the tracks.
+    if let Some(first) = plans.first() {
+        waypoint(&mut gpx, &first.depot, "Depot", "depot");
+    }
+    for plan in plans {
+        for stop in &plan.order {
+            let kind = match stop.kind {
+                StopKind::Dropoff => "dropoff",
+                StopKind::Pickup => "pickup",
+            };
+            waypoint(&mut gpx, &stop.location, &stop.item, kind);
+        }
+    }
+    for (i, plan) in plans.iter().enumerate() {
+        writeln!(gpx, "  <rte>\n    <name>Vehicle {}</name>", i + 1).unwrap();
+        for (point, command) in command_points(plan) {
+            writeln!(
+                gpx,
+                "    <rtept {}>\n      <desc>{}</desc>\n    </rtept>",
+                lat_lon(&point),
+                escape(&command.to_string())
+            )
+            .unwrap();
+        }
+        gpx.push_str("  </rte>\n");
+    }
+    for (i, plan) in plans.iter().enumerate() {
+        writeln!(gpx, "  <trk>\n    <name>Vehicle {}</name>", i + 1).unwrap();
+        for leg in plan.legs.iter().filter(|leg| !leg.route.is_empty()) {
+            gpx.push_str("    <trkseg>\n");
+            track_point(&mut gpx, &leg.route[0].start);
+            for segment in &leg.route {
+                track_point(&mut gpx, &segment.end);
+            }
+            gpx.push_str("    </trkseg>\n");
+        }
+        gpx.push_str("  </trk>\n");
+    }
+    gpx.push_str("</gpx>");
+    gpx
+}
+
+// * Where each command happens.  Commands were made from the legs' segments in order: a proceed
+// * covers the run of segments along its street, a turn comes where the next one starts, and
+// * whatever happens at a stop, happens where the leg ends.
+fn command_points(plan: &DeliveryPlan) -> Vec<(GeoCoord, &DeliveryCommand)> {
+    let mut points = Vec::new();
+    let mut here = plan.depot.clone();
+    for leg in &plan.legs {
+        let mut route: &[StreetSegment] = &leg.route;
+        for command in &plan.commands[leg.commands.clone()] {
+            match (command.kind(), route.first()) {
+                ("proceed", Some(first)) => {
+                    points.push((first.start.clone(), command));
+                    let run = route
+                        .iter()
+                        .take_while(|segment| segment.name == command.street_name())
+                        .count()
+                        .max(1);
+                    here = route[run - 1].end.clone();
+                    route = &route[run..];
+                }
+                ("turn", Some(next)) => points.push((next.start.clone(), command)),
+                _ => points.push((here.clone(), command)),
+            }
+        }
+        if let Some(last) = leg.route.last() {
+            here = last.end.clone();
+        }
+    }
+    points
+}
+
+fn waypoint(gpx: &mut String, gc: &GeoCoord, name: &str, kind: &str) {
+    writeln!(
+        gpx,
+        "  <wpt {}>\n    <name>{}</name>\n    <type>{}</type>\n  </wpt>",
+        lat_lon(gc),
+        escape(name),
+        kind
+    )
+    .unwrap();
+}
+
+fn track_point(gpx: &mut String, gc: &GeoCoord) {
+    writeln!(gpx, "      <trkpt {}/>", lat_lon(gc)).unwrap();
+}
+
+// Coordinates go out exactly as the map file wrote them.
+fn lat_lon(gc: &GeoCoord) -> String {
+    format!("lat=\"{}\" lon=\"{}\"", gc.lat_text().trim(), gc.lon_text().trim())
+}
@@ -0,0 +1,144 @@
+use crate::tour::{CostTable, DEPOT};
+
+// * The most stops solve is ever asked to take.  At 20 its tables already hold 2^20 * 20 entries
+// * each, over 300MB between them, and every stop past that doubles it.
+pub const MAX_STOPS: usize = 20;
+
+// * Held-Karp dynamic programming: the shortest tour out of the depot, through every stop and back.
+// * best[visited][last] is the shortest way to visit the set of stops, finishing at last.  That's
+// * O(2^n * n^2) time and O(2^n * n) memory, so it's only for a dozen or so stops.
+// * before[node] names a stop that has to be visited ahead of node, e.g. the pickup for a drop-off.
+// * Returns the stops in order with the tour's length, or None if no order satisfies before.
+pub fn solve(costs: &CostTable, stops: usize, before: &[Option<usize>]) -> Option<(Vec<usize>, f64)> {
+    if stops == 0 {
+        return Some((Vec::new(), 0.));
+    }
+    // Stop s is node s + 1 and bit s of a set.
+    let bit = |node: usize| 1usize << (node - 1);
+    let sets = 1usize << stops;
+    let at = |set: usize, last: usize| set * stops + (last - 1);
+    let mut best = vec![f64::INFINITY; sets * stops];
+    let mut came_from = vec![DEPOT; sets * stops];
+
+    for node in 1..=stops {
+        if before[node].is_none() {
+            best[at(bit(node), node)] = costs.get(DEPOT, node);
+        }
+    }
+    for set in 1..sets {
+        for last in 1..=stops {
+            let so_far = best[at(set, last)];
+            if set & bit(last) == 0 || so_far.is_infinite() {
+                continue;
+            }
+            for next in 1..=stops {
+                if set & bit(next) != 0 || before[next].is_some_and(|first| set & bit(first) == 0) {
+                    continue;
+                }
+                let extended = set | bit(next);
+                let cost = so_far + costs.get(last, next);
+                if cost < best[at(extended, next)] {
+                    best[at(extended, next)] = cost;
+                    came_from[at(extended, next)] = last;
+                }
+            }
+        }
+    }
+
+    let everything = sets - 1;
+    let (mut last, length) = (1..=stops)
+        .map(|last| (last, best[at(everything, last)] + costs.get(last, DEPOT)))
+        .min_by(|a, b| a.1.total_cmp(&b.1))?;
+    if length.is_infinite() {
+        return None;
+    }
+    let mut order = Vec::with_capacity(stops);
+    let mut set = everything;
+    while last != DEPOT {
+        order.push(last);
+        let previous = came_from[at(set, last)];
+        set &= !bit(last);
+        last = previous;
+    }
+    order.reverse();
+    Some((order, length))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lower_bound::held_karp_bound;
+    use crate::tour::Tour;
+
+    fn respects(order: &[usize], before: &[Option<usize>]) -> bool {
+        order.iter().enumerate().all(|(p, node)| {
+            before[*node].is_none_or(|first| order[..p].contains(&first))
+        })
+    }
+
+    // Every order of the stops, tried one by one.
+    fn brute_force(costs: &CostTable, stops: usize, before: &[Option<usize>]) -> Option<f64> {
+        fn orders(order: &mut Vec<usize>, left: &mut Vec<usize>, visit: &mut dyn FnMut(&[usize])) {
+            if left.is_empty() {
+                visit(order);
+            }
+            for p in 0..left.len() {
+                order.push(left.remove(p));
+                orders(order, left, visit);
+                left.insert(p, order.pop().unwrap());
+            }
+        }
+        let mut best: Option<f64> = None;
+        let mut left: Vec<usize> = (1..=stops).collect();
+        orders(&mut Vec::new(), &mut left, &mut |order| {
+            if respects(order, before) {
+                let length = Tour::new(order, costs).length();
+                best = Some(best.map_or(length, |best| best.min(length)));
+            }
+        });
+        best
+    }
+
+    #[test]
+    fn solve_matches_brute_force() {
+        let rng = fastrand::Rng::new();
+        rng.seed(42);
+        for _ in 0..100 {
+            let stops = rng.usize(1..=7);
+            let size = stops + 1;
+            // Each direction costs something different, as with one-way streets.
+            let legs: Vec<f64> = (0..size * size).map(|_| rng.f64() * 10.).collect();
+            let costs = CostTable::build(size, |from, to| {
+                if from == to {
+                    0.
+                } else {
+                    legs[from * size + to]
+                }
+            });
+
+            let free = vec![None; size];
+            let (order, length) = solve(&costs, stops, &free).unwrap();
+            let optimum = brute_force(&costs, stops, &free).unwrap();
+            assert!((length - optimum).abs() < 1e-9, "{} != {}", length, optimum);
+            assert!((Tour::new(&order, &costs).length() - length).abs() < 1e-9);
+            assert!(held_karp_bound(&costs) <= optimum + 1e-9);
+
+            // Some stops have to come after another, which now and then can't be done at all.
+            let mut before = vec![None; size];
+            for (node, after) in before.iter_mut().enumerate().skip(1) {
+                let first = rng.usize(1..=stops);
+                if first != node && rng.bool() {
+                    *after = Some(first);
+                }
+            }
+            match (solve(&costs, stops, &before), brute_force(&costs, stops, &before)) {
+                (Some((order, length)), Some(optimum)) => {
+                    assert!((length - optimum).abs() < 1e-9, "{} != {}", length, optimum);
+                    assert!(respects(&order, &before));
+                }
+                (None, None) => {}
+                (solved, brute) => panic!("{:?} but brute force found {:?}", solved, brute),
+            }
+        }
+    }
+}
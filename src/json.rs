@@ -0,0 +1,279 @@
+use crate::error::{DeliveryFailure, RouteError};
+use crate::planner::{DeliveryPlan, FleetPlan, LegEnd};
+use crate::provided::{DeliveryCommand, DeliveryRequest, StopKind};
+use serde::Serialize;
+
+// * Bump whenever a field is renamed, removed or changes meaning.  Adding fields doesn't.
+const SCHEMA_VERSION: u32 = 1;
+
+// * The --format json output.  Its shape is part of the program's interface, so these structs
+// * are kept apart from the planner's own types; the README documents every field.
+#[derive(Serialize)]
+struct PlanReport<'a> {
+    schema_version: u32,
+    ok: bool,
+    total_distance: f64,
+    longest_distance: f64,
+    lower_bound: f64,
+    vehicles: Vec<VehicleReport<'a>>,
+    rejected: Vec<StopReport<'a>>,
+}
+
+#[derive(Serialize)]
+struct ErrorReport {
+    schema_version: u32,
+    ok: bool,
+    error: ErrorDetails,
+}
+
+#[derive(Serialize)]
+struct ErrorDetails {
+    kind: &'static str,
+    message: String,
+}
+
+#[derive(Serialize)]
+struct VehicleReport<'a> {
+    vehicle: usize,
+    distance: f64,
+    lower_bound: f64,
+    optimal: bool,
+    visiting_order: Vec<StopReport<'a>>,
+    legs: Vec<LegReport>,
+    commands: Vec<CommandReport<'a>>,
+    snaps: Vec<SnapReport<'a>>,
+}
+
+#[derive(Serialize)]
+struct StopReport<'a> {
+    item: &'a str,
+    kind: &'static str,
+    latitude: f64,
+    longitude: f64,
+    quantity: u32,
+}
+
+#[derive(Serialize)]
+struct LegReport {
+    index: usize,
+    end: &'static str,
+    stop: Option<usize>,
+    distance: f64,
+    // First command and one past the last, as indices into the vehicle's commands.
+    commands: [usize; 2],
+}
+
+#[derive(Serialize)]
+struct CommandReport<'a> {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    direction: Option<&'a str>,
+    street: Option<&'a str>,
+    distance: Option<f64>,
+    item: Option<&'a str>,
+    eta: Option<f64>,
+}
+
+#[derive(Serialize)]
+struct SnapReport<'a> {
+    stop: &'a str,
+    distance: f64,
+}
+
+pub fn plan_json(plan: &DeliveryPlan) -> String {
+    to_json(&PlanReport {
+        schema_version: SCHEMA_VERSION,
+        ok: true,
+        total_distance: plan.distance,
+        longest_distance: plan.distance,
+        lower_bound: plan.lower_bound,
+        vehicles: vec![vehicle_report(1, plan)],
+        rejected: plan.rejected.iter().map(stop_report).collect(),
+    })
+}
+
+pub fn fleet_json(fleet: &FleetPlan) -> String {
+    to_json(&PlanReport {
+        schema_version: SCHEMA_VERSION,
+        ok: true,
+        total_distance: fleet.total_distance,
+        longest_distance: fleet.longest_distance,
+        lower_bound: fleet.lower_bound,
+        vehicles: fleet
+            .vehicles
+            .iter()
+            .enumerate()
+            .map(|(i, plan)| vehicle_report(i + 1, plan))
+            .collect(),
+        rejected: fleet.rejected.iter().map(stop_report).collect(),
+    })
+}
+
+pub fn error_json(error: &RouteError) -> String {
+    let kind = match error.kind() {
+        DeliveryFailure::BadCoord => "bad_coord",
+        DeliveryFailure::NoRoute => "no_route",
+        DeliveryFailure::NoVehicles => "no_vehicles",
+        DeliveryFailure::OverCapacity => "over_capacity",
+        DeliveryFailure::Other => "other",
+    };
+    to_json(&ErrorReport {
+        schema_version: SCHEMA_VERSION,
+        ok: false,
+        error: ErrorDetails {
+            kind,
+            message: error.to_string().trim_end().to_string(),
+        },
+    })
+}
+
+fn to_json(report: &impl Serialize) -> String {
+    serde_json::to_string_pretty(report).expect("plan reports always serialize")
+}
+
+fn vehicle_report(vehicle: usize, plan: &DeliveryPlan) -> VehicleReport<'_> {
+    VehicleReport {
+        vehicle,
+        distance: plan.distance,
+        lower_bound: plan.lower_bound,
+        optimal: plan.optimal,
+        visiting_order: plan.order.iter().map(stop_report).collect(),
+        legs: plan
+            .legs
+            .iter()
+            .enumerate()
+            .map(|(index, leg)| LegReport {
+                index,
+                end: match leg.end {
+                    LegEnd::Stop(_) => "stop",
+                    LegEnd::Reload => "reload",
+                    LegEnd::Finish => "finish",
+                },
+                stop: match leg.end {
+                    LegEnd::Stop(stop) => Some(stop),
+                    _ => None,
+                },
+                distance: leg.distance,
+                commands: [leg.commands.start, leg.commands.end],
+            })
+            .collect(),
+        commands: plan.commands.iter().map(command_report).collect(),
+        snaps: plan
+            .snaps
+            .iter()
+            .map(|(stop, distance)| SnapReport {
+                stop,
+                distance: *distance,
+            })
+            .collect(),
+    }
+}
+
+fn stop_report(stop: &DeliveryRequest) -> StopReport<'_> {
+    StopReport {
+        item: &stop.item,
+        kind: match stop.kind {
+            StopKind::Dropoff => "dropoff",
+            StopKind::Pickup => "pickup",
+        },
+        latitude: stop.location.latitude(),
+        longitude: stop.location.longitude(),
+        quantity: stop.demand,
+    }
+}
+
+// Fields that don't apply to a command's type are null.
+fn command_report(command: &DeliveryCommand) -> CommandReport<'_> {
+    CommandReport {
+        kind: command.kind(),
+        direction: non_empty(command.direction()),
+        street: non_empty(command.street_name()),
+        distance: Some(command.distance()).filter(|_| command.kind() == "proceed"),
+        item: non_empty(command.item()),
+        eta: command.eta(),
+    }
+}
+
+fn non_empty(text: &str) -> Option<&str> {
+    Some(text).filter(|text| !text.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::planner::Leg;
+    use crate::provided::GeoCoord;
+    use serde_json::{json, Value};
+
+    // One sandwich delivered a block up Main Street, and back.
+    fn small_plan() -> DeliveryPlan {
+        let depot = GeoCoord::from("34.0600", "-118.4400");
+        let stop = DeliveryRequest::from("Sandwich", &GeoCoord::from("34.0610", "-118.4400"));
+        DeliveryPlan {
+            commands: vec![
+                DeliveryCommand::new_proceed("north".to_string(), "Main Street".to_string(), 0.07),
+                DeliveryCommand::new_deliver("Sandwich".to_string()),
+                DeliveryCommand::new_proceed("south".to_string(), "Main Street".to_string(), 0.07),
+            ],
+            legs: vec![leg(LegEnd::Stop(0), 0..2), leg(LegEnd::Finish, 2..3)],
+            snaps: vec![("Depot".to_string(), 0.), ("Sandwich".to_string(), 0.01)],
+            order: vec![stop],
+            depot,
+            distance: 0.14,
+            lower_bound: 0.14,
+            rejected: Vec::new(),
+            optimal: true,
+            stats: Vec::new(),
+        }
+    }
+
+    fn leg(end: LegEnd, commands: std::ops::Range<usize>) -> Leg {
+        Leg {
+            end,
+            distance: 0.07,
+            route: Vec::new(),
+            commands,
+        }
+    }
+
+    #[test]
+    fn plan_json_keeps_its_schema() {
+        let report: Value = serde_json::from_str(&plan_json(&small_plan())).unwrap();
+        // serde_json hands keys back sorted.
+        let keys: Vec<&str> = report.as_object().unwrap().keys().map(String::as_str).collect();
+        assert_eq!(
+            keys,
+            [
+                "longest_distance",
+                "lower_bound",
+                "ok",
+                "rejected",
+                "schema_version",
+                "total_distance",
+                "vehicles"
+            ]
+        );
+        assert_eq!(report["schema_version"], SCHEMA_VERSION);
+        assert_eq!(report["ok"], true);
+
+        let vehicle = &report["vehicles"][0];
+        assert_eq!(vehicle["vehicle"], 1);
+        assert_eq!(
+            vehicle["commands"][0],
+            json!({
+                "type": "proceed",
+                "direction": "north",
+                "street": "Main Street",
+                "distance": 0.07,
+                "item": null,
+                "eta": null,
+            })
+        );
+        assert_eq!(
+            vehicle["legs"][0],
+            json!({ "index": 0, "end": "stop", "stop": 0, "distance": 0.07, "commands": [0, 2] })
+        );
+        assert_eq!(vehicle["visiting_order"][0]["item"], "Sandwich");
+        assert_eq!(vehicle["snaps"][1], json!({ "stop": "Sandwich", "distance": 0.01 }));
+    }
+}
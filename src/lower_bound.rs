@@ -0,0 +1,102 @@
+use crate::tour::{CostTable, DEPOT};
+
+// Rounds of penalty adjustment before settling for the best bound so far.
+const ROUNDS: usize = 200;
+// How fast the penalty adjustments shrink from one round to the next.
+const STEP_DECAY: f64 = 0.97;
+
+// * The Held-Karp lower bound: no tour through every node can be shorter than this.
+// * A 1-tree is a spanning tree over every stop plus the depot's two cheapest legs.  Every tour
+// * is a 1-tree, so the cheapest 1-tree can't cost more than the shortest tour.  Nodes also get
+// * penalties added to every leg touching them, which change every tour's length by the same
+// * amount but not every 1-tree's, so nodes the tree uses too often are made dearer and those
+// * it uses too little cheaper, round after round, and the highest bound seen wins.
+// * Legs use the cheaper of their two directions, so one-way streets only loosen the bound.
+pub fn held_karp_bound(costs: &CostTable) -> f64 {
+    let size = costs.size();
+    match size {
+        0 | 1 => return 0.,
+        2 => return costs.get(DEPOT, 1) + costs.get(1, DEPOT),
+        _ => {}
+    }
+    let leg = |a: usize, b: usize| costs.get(a, b).min(costs.get(b, a));
+    let mut penalty = vec![0.; size];
+    let mut best = f64::NEG_INFINITY;
+    let mut step = 0.;
+    for round in 0..ROUNDS {
+        let (length, degree) = one_tree(size, |a, b| leg(a, b) + penalty[a] + penalty[b]);
+        let bound = length - 2. * penalty.iter().sum::<f64>();
+        best = best.max(bound);
+        if degree.iter().all(|&d| d == 2) {
+            // * The 1-tree is a tour, so no tour is shorter.
+            break;
+        }
+        if round == 0 {
+            step = 0.5 * length / size as f64;
+        }
+        for node in 0..size {
+            penalty[node] += step * (degree[node] as f64 - 2.);
+        }
+        step *= STEP_DECAY;
+    }
+    best
+}
+
+// * A bound for routes that don't have to come back, given a table whose legs into the depot
+// * say where routes finish instead.  Whatever paths a fleet drives out of the depot, together
+// * they connect every stop to it, so they can't be shorter than the cheapest tree that does.
+// * Legs out of the depot count as driven from it; the rest in their cheaper direction, and the
+// * drive from the last stop to wherever the route finishes isn't counted at all.
+pub fn spanning_tree_bound(costs: &CostTable) -> f64 {
+    let leg = |a: usize, b: usize| match (a, b) {
+        (DEPOT, other) | (other, DEPOT) => costs.get(DEPOT, other),
+        _ => costs.get(a, b).min(costs.get(b, a)),
+    };
+    spanning_tree(costs.size(), DEPOT, leg).0
+}
+
+// The cheapest 1-tree's length, and how many of its legs touch each node.
+fn one_tree(size: usize, leg: impl Fn(usize, usize) -> f64) -> (f64, Vec<usize>) {
+    let (mut length, mut degree) = spanning_tree(size, 1, &leg);
+    let mut depot_legs: Vec<usize> = (1..size).collect();
+    depot_legs.sort_by(|&a, &b| leg(DEPOT, a).total_cmp(&leg(DEPOT, b)));
+    for &node in &depot_legs[..2] {
+        length += leg(DEPOT, node);
+        degree[node] += 1;
+    }
+    degree[DEPOT] = 2;
+    (length, degree)
+}
+
+// * Prim's algorithm over nodes first..size: the cheapest tree's length, and how many of its legs
+// * touch each node.
+fn spanning_tree(size: usize, first: usize, leg: impl Fn(usize, usize) -> f64) -> (f64, Vec<usize>) {
+    let mut degree = vec![0; size];
+    let mut length = 0.;
+    let mut in_tree = vec![false; size];
+    let mut distance = vec![f64::INFINITY; size];
+    let mut parent = vec![first; size];
+    if first >= size {
+        return (length, degree);
+    }
+    distance[first] = 0.;
+    for _ in first..size {
+        let node = (first..size)
+            .filter(|&node| !in_tree[node])
+            .min_by(|&a, &b| distance[a].total_cmp(&distance[b]))
+            .unwrap();
+        in_tree[node] = true;
+        if node != first {
+            length += distance[node];
+            degree[node] += 1;
+            degree[parent[node]] += 1;
+        }
+        for other in first..size {
+            if !in_tree[other] && leg(node, other) < distance[other] {
+                distance[other] = leg(node, other);
+                parent[other] = node;
+            }
+        }
+    }
+    (length, degree)
+}
@@ -1,43 +1,296 @@
+mod annealing;
+mod binary;
+mod construction;
+mod contraction;
+mod distance_matrix;
 mod optimizer;
+mod osm;
 mod planner;
+mod ejection_chain;
 mod error;
+mod geojson;
+mod gpx;
+mod held_karp;
+mod json;
+mod lower_bound;
 mod point_router;
 mod provided;
+mod schedule;
 mod street_map;
-use planner::DeliveryPlanner;
+mod svg;
+mod tour;
+mod xml;
+use annealing::{Acceptance, Cooling, OptimizerConfig, SimulatedAnnealing};
+use construction::{Christofides, NearestNeighbour};
+use contraction::ContractionHierarchy;
+use ejection_chain::StemAndCycle;
+use optimizer::TourOptimizer;
+use planner::{DeliveryPlan, DeliveryPlanner, FleetObjective, RouteEnd};
+use point_router::RouteSearch;
 use provided::{DeliveryRequest, GeoCoord};
+use schedule::{parse_clock, LatePolicy, Schedule, Speeds};
+use std::collections::HashMap;
 use std::fs::File;
 use std::io::{BufRead, BufReader};
 use std::path::Path;
+use std::time::Duration;
 use street_map::StreetMap;
 
+// Command line options.  Flags may come anywhere; the map and deliveries files are required.
+struct Options {
+    map_file: String,
+    deliveries_file: String,
+    hierarchy_file: Option<String>,
+    route_search: RouteSearch,
+    vehicles: usize,
+    objective: FleetObjective,
+    capacity: Option<u32>,
+    departure: Option<f64>,
+    speed: Option<f64>,
+    speeds_file: Option<String>,
+    late_policy: LatePolicy,
+    end: RouteEnd,
+    strategy: Box<dyn TourOptimizer>,
+    stats: bool,
+    format: OutputFormat,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum OutputFormat {
+    // Directions for a driver to read.
+    Text,
+    // The whole plan for other programs, as documented in the README.
+    Json,
+    // The route's geometry, for map viewers.
+    GeoJson,
+    // The route and directions, for GPS units.
+    Gpx,
+    // A drawing of the map and the route, from the render subcommand.
+    Svg,
+}
+
+const USAGE_OPTIONS: &str = "Options:
+  --ch FILE                    Route with a contraction hierarchy cached in FILE
+  --bidirectional              Search for directions from both ends at once (without --ch)
+  --vehicles N                 Split the deliveries among N vehicles (at least 1)
+  --objective total|longest    What a fleet plan keeps small
+  --open                       Finish each route at its last delivery instead of the depot
+  --end LAT LON                Finish each route at the given coordinate
+  --capacity N                 How much each vehicle can carry
+  --depart HHMM                When vehicles leave the depot; enables ETAs and time windows
+  --speed MPH                  Average driving speed (25 if not given)
+  --speeds FILE                Per-street speeds, one \"STREET:MPH\" per line
+  --late minimize|reject       Minimize lateness, or leave out deliveries that can't be on time
+  --strategy annealing|nearest|christofides|stem-cycle
+                               How to order the stops (annealing by default)
+  --exact N                    Find the shortest order outright for up to N stops (12 by default,
+                               at most 20)
+  --threads N                  Run N annealing chains side by side (one per core by default)
+  --stats                      Report how each annealing chain went
+  --format text|json|geojson|gpx
+                               Print directions, the whole plan as JSON, or the route as
+                               GeoJSON or GPX (text by default)
+  --seed N                     Make the plan reproducible: the same input and seed give the same output
+  --time-limit SECONDS         Stop searching for a better order after this long
+  --max-moves N                Stop each annealing chain after trying N moves
+  --cooling geometric|linear|reheat
+                               How the annealing temperature falls (geometric by default)
+  --metropolis                 Take worse orders by how much worse they are, not at a flat rate";
+
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     let args: Vec<String> = std::env::args().collect::<Vec<String>>();
 
-    if args.len() != 3 {
-        println!("Usage: {} [MAP-DATA] [DELIVERIES]", args[0]);
-        return Ok(());
+    if args.get(1).map(String::as_str) == Some("compile-map") {
+        return compile_map(&args[2..]);
     }
+    // * render takes the same options as planning, and draws the plan instead of printing it.
+    let render = args.get(1).map(String::as_str) == Some("render");
+    let plan_args: Vec<String> = match render {
+        true => args[..1].iter().chain(&args[2..]).cloned().collect(),
+        false => args.clone(),
+    };
+
+    let options = match parse_args(&plan_args) {
+        Some(mut options) => {
+            if render {
+                options.format = OutputFormat::Svg;
+            }
+            options
+        }
+        None => {
+            println!("Usage: {} [OPTIONS] [MAP-DATA] [DELIVERIES]", args[0]);
+            println!("       {} render [OPTIONS] [MAP-DATA] [DELIVERIES] > ROUTE.svg", args[0]);
+            println!("       {} compile-map MAP-DATA [OUTPUT]", args[0]);
+            println!("{}", USAGE_OPTIONS);
+            return Ok(());
+        }
+    };
 
     // Consider rewriting street_map so that file opening is done in main.
 
-    let sm = StreetMap::load_from(&args[1])?;
+    let sm = StreetMap::load_from(&options.map_file)?;
+
+    let (depot, deliveries) = load_deliveries(&options.deliveries_file)?;
 
-    let (depot, deliveries) = load_deliveries(&args[2])?;
+    let hierarchy = options
+        .hierarchy_file
+        .as_ref()
+        .map(|path| load_or_build_hierarchy(path, &sm));
+
+    let mut planner = DeliveryPlanner::new(&sm);
+    if let Some(ch) = &hierarchy {
+        planner = planner.with_hierarchy(ch);
+    }
+    planner = planner.with_route_search(options.route_search);
+    if let Some(capacity) = options.capacity {
+        planner = planner.with_capacity(capacity);
+    }
+    planner = planner.with_strategy(options.strategy);
+    let finished = match options.end {
+        RouteEnd::Depot => "back at the depot",
+        RouteEnd::LastStop => "at the last stop",
+        RouteEnd::At(_) => "at the route's end",
+    };
+    let shared_end = matches!(options.end, RouteEnd::At(_));
+    planner = planner.with_end(options.end);
+    if let Some(departure) = options.departure {
+        let mut speeds = Speeds::new(options.speed.unwrap_or(Speeds::default().default_mph));
+        if let Some(path) = &options.speeds_file {
+            speeds.streets = load_speeds(path)?;
+        }
+        planner = planner.with_schedule(Schedule {
+            departure,
+            speeds,
+            late_policy: options.late_policy,
+        });
+    }
+
+    if options.format == OutputFormat::Json {
+        let stats = options.stats;
+        let json = if options.vehicles > 1 {
+            let fleet = planner.generate_fleet_plan(
+                depot,
+                deliveries,
+                options.vehicles,
+                options.objective,
+            );
+            fleet.map(|fleet| {
+                if stats {
+                    for (i, plan) in fleet.vehicles.iter().enumerate() {
+                        print_stats(&format!("Vehicle {}", i + 1), plan);
+                    }
+                }
+                json::fleet_json(&fleet)
+            })
+        } else {
+            planner.generate_plan(depot, deliveries).map(|plan| {
+                if stats {
+                    print_stats("Route", &plan);
+                }
+                json::plan_json(&plan)
+            })
+        };
+        println!("{}", json.unwrap_or_else(|e| json::error_json(&e)));
+        return Ok(());
+    }
+    if options.format != OutputFormat::Text {
+        let plans = if options.vehicles > 1 {
+            planner
+                .generate_fleet_plan(depot, deliveries, options.vehicles, options.objective)
+                .map(|fleet| fleet.vehicles)
+        } else {
+            planner.generate_plan(depot, deliveries).map(|plan| vec![plan])
+        };
+        match plans {
+            Ok(plans) => match options.format {
+                OutputFormat::Gpx => println!("{}", gpx::plan_gpx(&plans)),
+                OutputFormat::Svg => print!("{}", svg::render_svg(&sm, &plans)),
+                _ => println!("{}", geojson::plan_geojson(&plans)),
+            },
+            Err(e) => eprintln!("Error: {:?}", e),
+        }
+        return Ok(());
+    }
 
     println!("Generating route...\n\n");
 
-    let planner = DeliveryPlanner::new(&sm);
+    if options.vehicles > 1 {
+        match planner.generate_fleet_plan(depot, deliveries, options.vehicles, options.objective) {
+            Ok(fleet) => {
+                for (i, plan) in fleet.vehicles.iter().enumerate() {
+                    println!("Vehicle {}:", i + 1);
+                    if plan.commands.is_empty() {
+                        println!("Vehicle {} has nothing to deliver.\n", i + 1);
+                        continue;
+                    }
+                    for command in &plan.commands {
+                        println!("{}", command);
+                    }
+                    println!(
+                        "Vehicle {} is {} after {:.2} miles ({}).",
+                        i + 1,
+                        finished,
+                        plan.distance,
+                        gap(plan.distance, plan.lower_bound)
+                    );
+                    if plan.optimal {
+                        println!("No shorter order exists for vehicle {}'s stops.", i + 1);
+                    }
+                    println!();
+                }
+                println!("All vehicles are {} and your deliveries are done!", finished);
+                println!(
+                    "{:.2} miles travelled for all deliveries ({}); the longest route is {:.2} miles.",
+                    fleet.total_distance,
+                    gap(fleet.total_distance, fleet.lower_bound),
+                    fleet.longest_distance
+                );
+                // Every vehicle reports the depot and any route end, so only keep the first's.
+                let snaps = fleet.vehicles.iter().enumerate().flat_map(|(i, plan)| {
+                    let snaps = &plan.snaps[..];
+                    match i {
+                        0 => snaps,
+                        _ if shared_end => &snaps[1..snaps.len() - 1],
+                        _ => &snaps[1..],
+                    }
+                });
+                print_snaps(snaps);
+                print_rejected(&fleet.rejected);
+                if options.stats {
+                    for (i, plan) in fleet.vehicles.iter().enumerate() {
+                        print_stats(&format!("Vehicle {}", i + 1), plan);
+                    }
+                }
+            }
+            Err(e) => {
+                eprintln!("Error: {:?}", e);
+            }
+        }
+        // * No harmful exit.
+        return Ok(());
+    }
 
     let result = planner.generate_plan(depot, deliveries);
 
     match result {
-        Ok((commands, distance_travelled)) => {
-            for command in commands {
+        Ok(plan) => {
+            for command in &plan.commands {
                 println!("{}", command);
             }
-            println!("You are back at the depot and your deliveries are done!");
-            println!("{:.2} miles travelled for all deliveries.", distance_travelled);
+            println!("You are {} and your deliveries are done!", finished);
+            println!(
+                "{:.2} miles travelled for all deliveries ({}).",
+                plan.distance,
+                gap(plan.distance, plan.lower_bound)
+            );
+            if plan.optimal {
+                println!("No shorter order exists for these stops.");
+            }
+            print_snaps(plan.snaps.iter());
+            print_rejected(&plan.rejected);
+            if options.stats {
+                print_stats("Route", &plan);
+            }
         }
         Err(e) => {
             eprintln!("Error: {:?}", e);
@@ -47,6 +300,200 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 
+// * How far the miles driven might be from the fewest possible, as a share of the lower bound.
+// * The true gap to the best plan is somewhere between zero and this.
+fn gap(distance: f64, lower_bound: f64) -> String {
+    let percent = if lower_bound > 0. {
+        (distance / lower_bound - 1.).max(0.) * 100.
+    } else {
+        0.
+    };
+    format!("lower bound {:.2} miles, gap {:.1}%", lower_bound, percent)
+}
+
+fn print_snaps<'a>(snaps: impl Iterator<Item = &'a (String, f64)>) {
+    for (stop, snap_distance) in snaps {
+        if *snap_distance > 0. {
+            println!("{} was snapped {:.2} miles onto the street network.", stop, snap_distance);
+        }
+    }
+}
+
+fn print_rejected(rejected: &[DeliveryRequest]) {
+    for delivery in rejected {
+        println!("{} can't be delivered within its window and was left out.", delivery.item);
+    }
+}
+
+// Tuning output goes to stderr, out of the way of the directions.
+fn print_stats(label: &str, plan: &DeliveryPlan) {
+    if plan.optimal {
+        eprintln!("{}: solved exactly.", label);
+    }
+    for chain in &plan.stats {
+        eprintln!("{}: {}", label, chain);
+    }
+}
+
+fn parse_args(args: &[String]) -> Option<Options> {
+    let mut positional = Vec::new();
+    let mut hierarchy_file = None;
+    let mut route_search = RouteSearch::AStar;
+    let mut vehicles = 1;
+    let mut objective = FleetObjective::TotalDistance;
+    let mut capacity = None;
+    let mut departure = None;
+    let mut speed = None;
+    let mut speeds_file = None;
+    let mut late_policy = None;
+    let mut end = RouteEnd::Depot;
+    let mut strategy = String::from("annealing");
+    let mut optimizer = OptimizerConfig::default();
+    let mut stats = false;
+    let mut format = OutputFormat::Text;
+    let mut iter = args.iter().skip(1);
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--ch" => hierarchy_file = Some(iter.next()?.clone()),
+            "--bidirectional" => route_search = RouteSearch::Bidirectional,
+            "--vehicles" => vehicles = iter.next()?.parse().ok().filter(|&n: &usize| n > 0)?,
+            "--open" => end = RouteEnd::LastStop,
+            "--end" => {
+                let (lat, lon) = (iter.next()?, iter.next()?);
+                end = RouteEnd::At(parse_coord(&format!("{} {}", lat, lon))?);
+            }
+            "--capacity" => capacity = Some(iter.next()?.parse().ok()?),
+            "--depart" => departure = Some(parse_clock(iter.next()?)?),
+            "--speed" => speed = Some(iter.next()?.parse().ok().filter(|&mph: &f64| mph > 0.)?),
+            "--strategy" => strategy = iter.next()?.clone(),
+            "--exact" => {
+                optimizer.exact_threshold =
+                    iter.next()?.parse().ok().filter(|&n: &usize| n <= held_karp::MAX_STOPS)?
+            }
+            "--threads" => {
+                optimizer.threads = Some(iter.next()?.parse().ok().filter(|&n: &usize| n > 0)?)
+            }
+            "--stats" => stats = true,
+            "--format" => {
+                format = match iter.next()?.as_str() {
+                    "text" => OutputFormat::Text,
+                    "json" => OutputFormat::Json,
+                    "geojson" => OutputFormat::GeoJson,
+                    "gpx" => OutputFormat::Gpx,
+                    _ => return None,
+                }
+            }
+            "--seed" => optimizer.seed = Some(iter.next()?.parse().ok()?),
+            "--time-limit" => {
+                let seconds: f64 = iter.next()?.parse().ok().filter(|&s: &f64| s > 0.)?;
+                optimizer.time_limit = Some(Duration::from_secs_f64(seconds));
+            }
+            "--max-moves" => optimizer.max_moves = Some(iter.next()?.parse().ok()?),
+            "--cooling" => {
+                optimizer.cooling = match iter.next()?.as_str() {
+                    "geometric" => Cooling::Geometric { factor: 0.99 },
+                    "linear" => Cooling::Linear { step: 0.005 },
+                    "reheat" => Cooling::Reheating {
+                        factor: 0.99,
+                        stall: 200,
+                    },
+                    _ => return None,
+                }
+            }
+            "--metropolis" => optimizer.acceptance = Acceptance::Metropolis,
+            "--speeds" => speeds_file = Some(iter.next()?.clone()),
+            "--late" => {
+                late_policy = match iter.next()?.as_str() {
+                    "minimize" => Some(LatePolicy::MinimizeLateness),
+                    "reject" => Some(LatePolicy::RejectInfeasible),
+                    _ => return None,
+                }
+            }
+            "--objective" => {
+                objective = match iter.next()?.as_str() {
+                    "total" => FleetObjective::TotalDistance,
+                    "longest" => FleetObjective::LongestRoute,
+                    _ => return None,
+                }
+            }
+            _ if arg.starts_with("--") => return None,
+            _ => positional.push(arg.clone()),
+        }
+    }
+    if positional.len() != 2 {
+        return None;
+    }
+    // Speeds and lateness only mean something once there's a departure time.
+    if departure.is_none() && (speed.is_some() || speeds_file.is_some() || late_policy.is_some()) {
+        return None;
+    }
+    let strategy: Box<dyn TourOptimizer> = match strategy.as_str() {
+        "annealing" => Box::new(SimulatedAnnealing::new(optimizer)),
+        "nearest" => Box::new(NearestNeighbour),
+        "christofides" => Box::new(Christofides),
+        "stem-cycle" => Box::new(StemAndCycle),
+        _ => return None,
+    };
+    let deliveries_file = positional.pop().unwrap();
+    let map_file = positional.pop().unwrap();
+    Some(Options {
+        map_file,
+        deliveries_file,
+        hierarchy_file,
+        route_search,
+        vehicles,
+        objective,
+        capacity,
+        departure,
+        speed,
+        speeds_file,
+        late_policy: late_policy.unwrap_or(LatePolicy::MinimizeLateness),
+        end,
+        strategy,
+        stats,
+        format,
+    })
+}
+
+// * Parses a text map and saves it compiled, by default where load_from looks for it.
+fn compile_map(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let (map_file, output) = match args {
+        [map_file] => (map_file, StreetMap::compiled_path(map_file)),
+        [map_file, output] => (map_file, output.clone()),
+        _ => {
+            println!("Usage: compile-map MAP-DATA [OUTPUT]");
+            return Ok(());
+        }
+    };
+    let mut sm = StreetMap::new();
+    sm.load(map_file)?;
+    sm.save_compiled(&output, map_file)?;
+    println!(
+        "Compiled {} intersections and {} streets into {}.",
+        sm.node_count(),
+        sm.name_count(),
+        output
+    );
+    Ok(())
+}
+
+// * The hierarchy is slow to build, so it is kept on disk and only rebuilt when missing or stale.
+fn load_or_build_hierarchy(path: &str, sm: &StreetMap) -> ContractionHierarchy {
+    match ContractionHierarchy::load(path, sm) {
+        Ok(ch) => ch,
+        Err(e) => {
+            if e.kind() != std::io::ErrorKind::NotFound {
+                eprintln!("Rebuilding contraction hierarchy {}: {}", path, e);
+            }
+            let ch = ContractionHierarchy::build(sm);
+            if let Err(e) = ch.save(path) {
+                eprintln!("Could not save contraction hierarchy to {}: {}", path, e);
+            }
+            ch
+        }
+    }
+}
+
 fn load_deliveries(del_file: &str) -> Result<(GeoCoord, Vec<DeliveryRequest>), std::io::Error> {
     let file_path = Path::new(del_file);
     let file_handle = File::open(file_path)?;
@@ -64,24 +511,121 @@ fn load_deliveries(del_file: &str) -> Result<(GeoCoord, Vec<DeliveryRequest>), s
 
     let mut deliveries: Vec<DeliveryRequest> = Vec::new();
 
-    while let Some(request) = coords.next() {
+    for request in coords {
         if !request.contains(':') {
             eprintln!("Missing colon in deliveries - line: {}.", request);
             continue;
         }
         let parts: Vec<&str> = request.split(':').collect();
-        if parts[1] == "" {
+        if parts[1].is_empty() {
             eprintln!("Missing item in deliveries - line: {}", request);
             continue;
         }
-        let coords: Vec<&str> = parts[0].split_whitespace().collect();
-        if coords.len() != 2 {
-            eprintln!("Bad formatting in deliveries - line: {}", request);
-            continue;
+        // * "PLAT PLON > LAT LON" means the item is picked up at PLAT PLON on the way.
+        let (pickup, dropoff) = match parts[0].split_once('>') {
+            Some((pickup, dropoff)) => (parse_coord(pickup).map(Some), parse_coord(dropoff)),
+            None => (Some(None), parse_coord(parts[0])),
+        };
+        let (pickup, coord) = match (pickup, dropoff) {
+            (Some(pickup), Some(coord)) => (pickup, coord),
+            _ => {
+                eprintln!("Bad formatting in deliveries - line: {}", request);
+                continue;
+            }
+        };
+        let mut delivery = DeliveryRequest::from(parts[1], &coord);
+        delivery.pickup = pickup;
+        // * An optional third field gives the quantity, e.g. "34.06 -118.44:Tacos:3".
+        if let Some(quantity) = parts.get(2).filter(|quantity| !quantity.trim().is_empty()) {
+            match quantity.trim().parse() {
+                Ok(demand) => delivery.demand = demand,
+                Err(_) => {
+                    eprintln!("Bad quantity in deliveries - line: {}", request);
+                    continue;
+                }
+            }
+        }
+        // * An optional fourth field gives the window, e.g. "1130-1200", "-1200" or "1130-".
+        if let Some(window) = parts.get(3).filter(|window| !window.trim().is_empty()) {
+            match parse_window(window) {
+                Some((earliest, latest)) => {
+                    delivery.earliest = earliest;
+                    delivery.latest = latest;
+                }
+                None => {
+                    eprintln!("Bad time window in deliveries - line: {}", request);
+                    continue;
+                }
+            }
         }
-        let coord = GeoCoord::from(coords[0], coords[1]);
-        deliveries.push(DeliveryRequest::from(parts[1], &coord));
+        deliveries.push(delivery);
     }
 
     Ok((depot, deliveries))
 }
+
+fn parse_coord(text: &str) -> Option<GeoCoord> {
+    let coords: Vec<&str> = text.split_whitespace().collect();
+    match coords[..] {
+        [lat, lon] if lat.parse::<f64>().is_ok() && lon.parse::<f64>().is_ok() => {
+            Some(GeoCoord::from(lat, lon))
+        }
+        _ => None,
+    }
+}
+
+fn parse_window(window: &str) -> Option<(Option<f64>, Option<f64>)> {
+    let (earliest, latest) = window.split_once('-')?;
+    let bound = |text: &str| {
+        if text.trim().is_empty() {
+            Some(None)
+        } else {
+            parse_clock(text).map(Some)
+        }
+    };
+    Some((bound(earliest)?, bound(latest)?))
+}
+
+// Each line is a street name and its speed in miles per hour, e.g. "Wilshire Boulevard:35".
+fn load_speeds(speeds_file: &str) -> Result<HashMap<String, f64>, std::io::Error> {
+    let contents = BufReader::new(File::open(Path::new(speeds_file))?);
+    let mut speeds = HashMap::new();
+    for line in contents.lines() {
+        let line = line?;
+        match line.rsplit_once(':').map(|(name, mph)| (name, mph.trim().parse::<f64>())) {
+            Some((name, Ok(mph))) if mph > 0. => {
+                speeds.insert(name.trim().to_string(), mph);
+            }
+            _ => eprintln!("Bad speed - line: {}", line),
+        }
+    }
+    Ok(speeds)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn windows_may_leave_either_end_open() {
+        assert_eq!(parse_window("0900-1100"), Some((Some(540.), Some(660.))));
+        assert_eq!(parse_window("-1100"), Some((None, Some(660.))));
+        assert_eq!(parse_window("0900-"), Some((Some(540.), None)));
+        assert_eq!(parse_window("-"), Some((None, None)));
+        for bad in ["0900", "0900-2400", "noon-1100", "0900-1100-1200"] {
+            assert_eq!(parse_window(bad), None, "{:?}", bad);
+        }
+    }
+
+    #[test]
+    fn exact_solving_is_capped() {
+        let args = |exact: &str| {
+            let args = ["goober_eats", "--exact", exact, "map.txt", "deliveries.txt"];
+            args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>()
+        };
+        assert!(parse_args(&args("0")).is_some());
+        assert!(parse_args(&args("20")).is_some());
+        assert!(parse_args(&args("21")).is_none());
+        assert!(parse_args(&args("40")).is_none());
+    }
+}
@@ -1,129 +1,457 @@
+use crate::annealing::ChainStats;
+use crate::distance_matrix::DistanceMatrix;
+use crate::lower_bound::{held_karp_bound, spanning_tree_bound};
+use crate::planner::RouteEnd;
 use crate::provided::*;
-// use std::thread;
-use fastrand;
-
-trait SimulatedAnnealing {
-    // ? Look into making this more efficient with Vec<usize> instead of Vec<DeliveryRequest>.
-    // ? This was attempted, but one error failed the build (trait not impl'd for &T).
-    // ? Look in the commit history.  A building attempt, later, turned out slow.
-    type BaseNode;
-    type VisitNode;
-
-    fn simulated_annealing(
-        depot: &Self::BaseNode,
-        deliveries: &Vec<Self::VisitNode>,
-    ) -> (Vec<Self::VisitNode>, f64);
-    fn iterate(
-        depot: &Self::BaseNode,
-        deliveries: &Vec<Self::VisitNode>,
-    ) -> (Vec<Self::VisitNode>, f64);
-    fn permute(deliveries: Vec<Self::VisitNode>) -> Vec<Self::VisitNode>;
+use crate::schedule::Schedule;
+use crate::tour::{CostTable, Tour, DEPOT};
+use std::collections::HashMap;
+
+// * A way of putting one vehicle's stops in order.  The planner will drive whatever order comes back.
+pub trait TourOptimizer: Sync {
+    fn optimize(&self, problem: &TourProblem) -> OptimizedOrder;
 }
 
-trait StemCycle {
-    // * Defines an interface for working with my stem_cycle graph library.
+// * Every minute a delivery is late costs as much as driving this many extra miles.
+const LATENESS_MILES_PER_MINUTE: f64 = 10.;
+// * Every unit carried beyond capacity costs this many extra miles.  Only pickups can cause it.
+const OVERLOAD_MILES_PER_UNIT: f64 = 100.;
+
+// * A delivery order, and whether it's known to be the best order there is.
+pub struct OptimizedOrder {
+    pub deliveries: Vec<DeliveryRequest>,
+    pub optimal: bool,
+    // One per annealing chain.  Empty for the other strategies, and when solved exactly.
+    pub stats: Vec<ChainStats>,
 }
 
-pub struct DeliveryOptimizer;
-
-impl DeliveryOptimizer {
-    pub fn new() -> Self {
-        DeliveryOptimizer {}
-    }
-    pub fn optimize_order(
-        &self,
-        depot: &GeoCoord,
-        deliveries: Vec<DeliveryRequest>,
-    ) -> (Vec<DeliveryRequest>, f64) {
-        Self::simulated_annealing(depot, &deliveries)
-    }
-    // * Calculating the real cost of a route, with the point_router, is expensive.  Add the functionality in if desired.
-    fn crow_cost(depot: &GeoCoord, deliveries: &Vec<DeliveryRequest>) -> f64 {
-        let mut crow_distance = 0.;
-        let mut current = depot;
-        for delivery in deliveries {
-            crow_distance += distance_earth_miles(current, &delivery.location);
-            current = &delivery.location;
+impl OptimizedOrder {
+    // An order found by a heuristic, with no claim to being the best.
+    pub fn heuristic(problem: &TourProblem, order: &[usize]) -> OptimizedOrder {
+        OptimizedOrder {
+            deliveries: problem.stops_in(order),
+            optimal: false,
+            stats: Vec::new(),
         }
-		crow_distance += distance_earth_miles(current, depot);
-        crow_distance
     }
 }
 
-impl SimulatedAnnealing for DeliveryOptimizer {
-    type BaseNode = GeoCoord;
-    type VisitNode = DeliveryRequest;
-
-    fn simulated_annealing(
-        depot: &GeoCoord,
-        deliveries: &Vec<DeliveryRequest>,
-    ) -> (Vec<DeliveryRequest>, f64) {
-        // TODO: Implement multi-threading on this, so that we can take the best of a few runs.
-        // ? Will need Arc<Mutex<T>>.
-        let (mut best_tour, mut best_cost) = Self::iterate(depot, deliveries);
-        for _ in 0..100 {
-            let (new_tour, new_cost) = Self::iterate(depot, &best_tour);
-            if new_cost < best_cost {
-                best_tour = new_tour;
-                best_cost = new_cost;
-            }
+// * Works out what tours cost, then leaves ordering the stops to a strategy.
+pub struct DeliveryOptimizer<'a> {
+    matrix: Option<&'a DistanceMatrix>,
+    capacity: Option<u32>,
+    schedule: Option<&'a Schedule>,
+    end: RouteEnd,
+    strategy: &'a dyn TourOptimizer,
+}
+
+impl<'a> DeliveryOptimizer<'a> {
+    // * Annealing settings belong to the strategy: SimulatedAnnealing::new(config).
+    pub fn new(strategy: &'a dyn TourOptimizer) -> Self {
+        DeliveryOptimizer {
+            matrix: None,
+            capacity: None,
+            schedule: None,
+            end: RouteEnd::Depot,
+            strategy,
         }
-        (best_tour, best_cost)
-    }
-    fn iterate(depot: &GeoCoord, deliveries: &Vec<DeliveryRequest>) -> (Vec<DeliveryRequest>, f64) {
-        let mut no_improvements = 0;
-        let size = deliveries.len();
-
-        let mut current_path = deliveries.clone();
-        let mut current_cost = Self::crow_cost(depot, &current_path);
-        let mut best_tour = deliveries.clone();
-        let mut best_cost = f64::MAX;
-
-        /* TODO: We can improve the accuracy better by not revisiting
-        old paths, by encoding vector order into a set.  It's better than
-        simply performing thousands of iterations. */
-        let limit = match size {
-            0..=10 => 50,
-            11..=100 => 1000,
-            _ => 2000,
-        };
-        let mut temperature = 0.9;
-        let rng = fastrand::Rng::new();
-
-        while no_improvements < limit {
-            let new_path = Self::permute(current_path.clone());
-            let new_cost = Self::crow_cost(depot, &new_path);
-            if new_cost < current_cost {
-                current_path = new_path;
-                current_cost = new_cost;
-                if new_cost < best_cost {
-                    best_cost = new_cost;
-                    best_tour = current_path.clone();
-                    no_improvements = 0;
-                }
+    }
+    // * Scores tours by driving distance.  The matrix must cover the depot and every delivery.
+    pub fn with_matrix(mut self, matrix: &'a DistanceMatrix) -> Self {
+        self.matrix = Some(matrix);
+        self
+    }
+    // * Tours then include trips back to the depot whenever the next delivery won't fit.
+    pub fn with_capacity(mut self, capacity: Option<u32>) -> Self {
+        self.capacity = capacity;
+        self
+    }
+    // * Tours are then also charged for every minute a delivery arrives after its window.
+    pub fn with_schedule(mut self, schedule: Option<&'a Schedule>) -> Self {
+        self.schedule = schedule;
+        self
+    }
+    // * Tours are then charged for getting to where routes finish, not back to the depot.  An end
+    // * coordinate has to be in the matrix too.
+    pub fn with_end(mut self, end: RouteEnd) -> Self {
+        self.end = end;
+        self
+    }
+    pub fn optimize_order(&self, depot: &GeoCoord, deliveries: Vec<DeliveryRequest>) -> OptimizedOrder {
+        self.strategy.optimize(&self.problem(depot, &deliveries))
+    }
+    // * No way of driving the deliveries, reloads and all, is shorter than this.  An order known
+    // * to be the best is its own bound.
+    pub fn lower_bound(&self, depot: &GeoCoord, order: &OptimizedOrder) -> f64 {
+        let problem = self.problem(depot, &order.deliveries);
+        if order.optimal {
+            let nodes: Vec<usize> = (1..=problem.stop_count()).collect();
+            Tour::new(&nodes, problem.costs()).length()
+        } else {
+            held_karp_bound(problem.costs())
+        }
+    }
+    // * The same for a whole fleet, however the deliveries are shared among its vehicles.  When
+    // * routes come back, they can be joined up at the depot into one tour, so a single vehicle's
+    // * bound holds.  Routes that don't can't be joined like that.
+    pub fn fleet_lower_bound(&self, depot: &GeoCoord, order: &OptimizedOrder) -> f64 {
+        match self.end {
+            RouteEnd::Depot => self.lower_bound(depot, order),
+            _ => spanning_tree_bound(self.problem(depot, &order.deliveries).costs()),
+        }
+    }
+    // * The cost of one vehicle driving each run of consecutive deliveries: tour[i..j] at [i][j].
+    pub fn run_costs(&self, depot: &GeoCoord, tour: &[DeliveryRequest]) -> Vec<Vec<f64>> {
+        let problem = self.problem(depot, tour);
+        let nodes: Vec<usize> = (1..=tour.len()).collect();
+        (0..tour.len())
+            .map(|i| {
+                (0..=tour.len())
+                    .map(|j| {
+                        if j > i {
+                            problem.cost(&nodes[i..j])
+                        } else {
+                            0.
+                        }
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+    // * Without a distance matrix, tours are scored as the crow flies.
+    fn problem<'p>(&'p self, depot: &GeoCoord, deliveries: &'p [DeliveryRequest]) -> TourProblem<'p> {
+        let location = |node: usize| {
+            if node == DEPOT {
+                depot
             } else {
-                let rand = rng.f32();
-                if rand < temperature {
-                    current_path = new_path;
+                &deliveries[node - 1].location
+            }
+        };
+        let size = deliveries.len() + 1;
+        let distance = |from: &GeoCoord, to: &GeoCoord| match self.matrix {
+            Some(matrix) => matrix.cost(from, to),
+            None => distance_earth_miles(from, to),
+        };
+        // * Legs into the depot are where the route finishes, and reload trips use to_depot.
+        let costs = CostTable::build(size, |a, b| match (&self.end, b) {
+            (RouteEnd::LastStop, DEPOT) => 0.,
+            (RouteEnd::At(end), DEPOT) => distance(location(a), end),
+            _ => distance(location(a), location(b)),
+        });
+        let to_depot = (0..size).map(|node| distance(location(node), depot)).collect();
+        let times = self.schedule.map(|schedule| {
+            CostTable::build(size, |a, b| match self.matrix {
+                Some(matrix) => matrix.time(location(a), location(b)),
+                None => {
+                    distance_earth_miles(location(a), location(b)) / schedule.speeds.default_mph * 60.
                 }
-                no_improvements += 1;
-                temperature *= 0.99;
+            })
+        });
+        let constrained = self.capacity.is_some()
+            || self.schedule.is_some()
+            || deliveries.iter().any(|delivery| delivery.pickup.is_some());
+        TourProblem {
+            deliveries,
+            costs,
+            to_depot,
+            times,
+            capacity: self.capacity,
+            schedule: self.schedule,
+            constrained,
+        }
+    }
+}
+
+// * One vehicle's stops, and what it costs to visit them in any order.  Stops are numbered as
+// * nodes (see tour::DEPOT), and orders are lists of nodes without the depot.
+pub struct TourProblem<'p> {
+    deliveries: &'p [DeliveryRequest],
+    // * The last leg of a tour, into the depot, goes wherever the route finishes.
+    costs: CostTable,
+    // Driving distances back to the depot to reload, by node.
+    to_depot: Vec<f64>,
+    // Driving minutes, only worked out with a schedule.
+    times: Option<CostTable>,
+    capacity: Option<u32>,
+    schedule: Option<&'p Schedule>,
+    constrained: bool,
+}
+
+impl TourProblem<'_> {
+    pub fn stop_count(&self) -> usize {
+        self.deliveries.len()
+    }
+    pub fn stop(&self, node: usize) -> &DeliveryRequest {
+        &self.deliveries[node - 1]
+    }
+    pub fn stops_in(&self, order: &[usize]) -> Vec<DeliveryRequest> {
+        order.iter().map(|&node| self.stop(node).clone()).collect()
+    }
+    // Driving distances between nodes.
+    pub fn costs(&self) -> &CostTable {
+        &self.costs
+    }
+    fn time(&self, from: usize, to: usize) -> f64 {
+        self.times.as_ref().map_or(0., |times| times.get(from, to))
+    }
+    // * Whether tours are charged for more than their length: reloads, lateness or pickups.
+    // * If not, a move's change in length is all there is to its change in cost.
+    pub fn is_constrained(&self) -> bool {
+        self.constrained
+    }
+    // * Whether the cost of a tour is only its length, so a dynamic program over (stops visited,
+    // * last stop) can find the best one.  Pickups still can, by refusing early drop-offs.
+    pub fn depends_only_on_length(&self) -> bool {
+        self.capacity.is_none() && self.schedule.is_none()
+    }
+    pub fn tour_cost(&self, tour: &Tour) -> f64 {
+        if self.is_constrained() {
+            self.cost(tour.order())
+        } else {
+            tour.length()
+        }
+    }
+    // * Distance, plus penalties for lateness and overloading.
+    // * Tours that drop an item off before picking it up can't be driven, and cost infinity.
+    pub fn cost(&self, order: &[usize]) -> f64 {
+        let stops: Vec<&DeliveryRequest> = order.iter().map(|&node| self.stop(node)).collect();
+        if !pairs_in_order(&stops) {
+            return f64::INFINITY;
+        }
+        let (reloads, overload) = load_plan(&stops, self.capacity);
+        let mut distance = 0.;
+        let mut current = DEPOT;
+        let mut clock = self.schedule.map_or(0., |schedule| schedule.departure);
+        let mut lateness = 0.;
+        for (i, &node) in order.iter().enumerate() {
+            if reloads.contains(&i) {
+                distance += self.to_depot[current];
+                clock += self.time(current, DEPOT);
+                current = DEPOT;
+            }
+            distance += self.costs.get(current, node);
+            clock += self.time(current, node);
+            if self.times.is_some() {
+                let stop = self.stop(node);
+                clock = clock.max(stop.earliest.unwrap_or(clock));
+                lateness += (clock - stop.latest.unwrap_or(clock)).max(0.);
+            }
+            current = node;
+        }
+        distance += self.costs.get(current, DEPOT);
+        distance
+            + lateness * LATENESS_MILES_PER_MINUTE
+            + overload as f64 * OVERLOAD_MILES_PER_UNIT
+    }
+    // The pickup node each drop-off has to follow, by node.  Identical requests share a pickup
+    // location, so it doesn't matter which of their pickups goes with which drop-off.
+    pub fn pickup_nodes(&self) -> Vec<Option<usize>> {
+        let mut pickups: HashMap<(&str, &GeoCoord), Vec<usize>> = HashMap::new();
+        for (i, stop) in self.deliveries.iter().enumerate() {
+            if let (Some(pickup), StopKind::Pickup) = (&stop.pickup, stop.kind) {
+                pickups.entry((&stop.item, pickup)).or_default().push(i + 1);
             }
         }
-        (best_tour, best_cost)
+        let mut before = vec![None; self.deliveries.len() + 1];
+        for (i, stop) in self.deliveries.iter().enumerate() {
+            if let (Some(pickup), StopKind::Dropoff) = (&stop.pickup, stop.kind) {
+                before[i + 1] = pickups
+                    .get_mut(&(stop.item.as_str(), pickup))
+                    .and_then(|nodes| nodes.pop());
+            }
+        }
+        before
     }
-    fn permute(mut deliveries: Vec<DeliveryRequest>) -> Vec<DeliveryRequest> {
-        let delivery_count = deliveries.len();
-        if delivery_count == 1 {
-            return deliveries.clone();
+}
+
+// * Fills the vehicle in tour order and heads back to the depot once the next delivery won't fit.
+// * Returns the positions in the tour that a reload comes just before.
+pub fn reload_points(deliveries: &[DeliveryRequest], capacity: Option<u32>) -> Vec<usize> {
+    let stops: Vec<&DeliveryRequest> = deliveries.iter().collect();
+    load_plan(&stops, capacity).0
+}
+
+// * Items from the depot ride along from the start of their trip until they're dropped off, while
+// * picked up items only ride between their two stops.  Returns the reload positions and the most
+// * the vehicle is ever over capacity, which reloading can't fix when pickups are to blame.
+fn load_plan(deliveries: &[&DeliveryRequest], capacity: Option<u32>) -> (Vec<usize>, u32) {
+    let capacity = match capacity {
+        Some(capacity) => capacity,
+        None => return (Vec::new(), 0),
+    };
+    let from_depot = |stop: &DeliveryRequest| stop.pickup.is_none();
+    let mut reloads = Vec::new();
+    // Over the current trip: depot items loaded, depot items dropped, picked up items on board, and
+    // the highest (on board - dropped) seen so far.  The load at any point is loaded - dropped + on board.
+    let (mut loaded, mut dropped, mut on_board, mut peak) = (0, 0i64, 0i64, 0i64);
+    for (i, stop) in deliveries.iter().enumerate() {
+        let demand = stop.demand as i64;
+        if from_depot(stop) {
+            // Loading this item too raises the load everywhere earlier in the trip.
+            if loaded > 0 && loaded + demand + peak > capacity as i64 {
+                reloads.push(i);
+                (loaded, dropped, peak) = (0, 0, on_board);
+            }
+            loaded += demand;
+            dropped += demand;
+        } else if stop.kind == StopKind::Pickup {
+            on_board += demand;
+        } else {
+            on_board -= demand;
         }
-        let (mut rand1, mut rand2) = (0, 0);
-        let rng = fastrand::Rng::new();
-        while rand1 == rand2 {
-            rand1 = rng.usize(..delivery_count);
-            rand2 = rng.usize(..delivery_count);
+        peak = peak.max(on_board - dropped);
+    }
+
+    let mut overload = 0;
+    let mut trip_start = 0;
+    for trip_end in reloads.iter().copied().chain([deliveries.len()]) {
+        let trip = &deliveries[trip_start..trip_end];
+        let mut load: i64 = trip
+            .iter()
+            .filter(|stop| from_depot(stop))
+            .map(|stop| stop.demand as i64)
+            .sum();
+        load += on_board_before(&deliveries[..trip_start]);
+        overload = overload.max(load - capacity as i64);
+        for stop in trip {
+            match (from_depot(stop), stop.kind) {
+                (false, StopKind::Pickup) => load += stop.demand as i64,
+                _ => load -= stop.demand as i64,
+            }
+            overload = overload.max(load - capacity as i64);
         }
-        deliveries.swap(rand1, rand2);
-        deliveries
+        trip_start = trip_end;
+    }
+    (reloads, overload as u32)
+}
+
+// Picked up items still on board after the given stops.
+fn on_board_before(stops: &[&DeliveryRequest]) -> i64 {
+    stops
+        .iter()
+        .filter(|stop| stop.pickup.is_some())
+        .map(|stop| match stop.kind {
+            StopKind::Pickup => stop.demand as i64,
+            StopKind::Dropoff => -(stop.demand as i64),
+        })
+        .sum()
+}
+
+// * Whether every item with a pickup is picked up before it's dropped off.  Identical requests
+// * are interchangeable, so pairs are matched by item and pickup location.
+fn pairs_in_order(deliveries: &[&DeliveryRequest]) -> bool {
+    let mut on_board: HashMap<(&str, &GeoCoord), usize> = HashMap::new();
+    for stop in deliveries {
+        if let Some(pickup) = &stop.pickup {
+            let count = on_board.entry((stop.item.as_str(), pickup)).or_default();
+            match stop.kind {
+                StopKind::Pickup => *count += 1,
+                StopKind::Dropoff if *count == 0 => return false,
+                StopKind::Dropoff => *count -= 1,
+            }
+        }
+    }
+    true
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::construction::NearestNeighbour;
+
+    fn stop(item: &str, lon: f64, demand: u32) -> DeliveryRequest {
+        DeliveryRequest {
+            demand,
+            ..DeliveryRequest::from(item, &GeoCoord::from_degrees(34.06, -118.44 + lon))
+        }
+    }
+
+    // * A pickup at a restaurant and its drop-off, both of the same item.
+    fn paired(item: &str, pickup_lon: f64, dropoff_lon: f64, demand: u32) -> [DeliveryRequest; 2] {
+        let dropoff = DeliveryRequest {
+            pickup: Some(GeoCoord::from_degrees(34.06, -118.44 + pickup_lon)),
+            ..stop(item, dropoff_lon, demand)
+        };
+        [dropoff.pickup_stop().unwrap(), dropoff]
+    }
+
+    #[test]
+    fn reloads_when_the_next_delivery_wont_fit() {
+        let tour: Vec<DeliveryRequest> =
+            [2, 2, 2, 3, 1].iter().map(|&demand| stop("Box", 0.001, demand)).collect();
+        assert_eq!(reload_points(&tour, None), Vec::<usize>::new());
+        assert_eq!(reload_points(&tour, Some(5)), [2, 4]);
+        assert_eq!(reload_points(&tour, Some(10)), Vec::<usize>::new());
+    }
+
+    #[test]
+    fn reloads_leave_picked_up_items_on_board() {
+        // * The tacos ride from their pickup to the end, so the trip after the reload can only
+        // * take 2 more boxes from the depot.
+        let [pickup, dropoff] = paired("Tacos", 0.001, 0.005, 2);
+        let tour = vec![pickup, stop("Box", 0.002, 2), stop("Box", 0.003, 2), dropoff];
+        assert_eq!(reload_points(&tour, Some(4)), [2]);
+    }
+
+    #[test]
+    fn pickups_over_capacity_are_charged_as_overload() {
+        let strategy = NearestNeighbour;
+        let optimizer = DeliveryOptimizer::new(&strategy).with_capacity(Some(3));
+        let [pickup, dropoff] = paired("Tacos", 0.001, 0.003, 2);
+        let deliveries = vec![pickup, stop("Box", 0.002, 2), dropoff];
+        let depot = GeoCoord::from_degrees(34.06, -118.44);
+        let problem = optimizer.problem(&depot, &deliveries);
+        let length = |order: &[usize]| {
+            let mut nodes = vec![DEPOT];
+            nodes.extend_from_slice(order);
+            nodes.push(DEPOT);
+            nodes.windows(2).map(|leg| problem.costs().get(leg[0], leg[1])).sum::<f64>()
+        };
+
+        // * Picking the tacos up with the box still on board makes 4, one over; dropping the box
+        // * first never carries more than 2.  Reloading can't help either way.
+        let stops: Vec<&DeliveryRequest> = deliveries.iter().collect();
+        assert_eq!(load_plan(&stops, Some(3)), (Vec::new(), 1));
+        let (overloaded, fits) = ([1, 2, 3], [2, 1, 3]);
+        let penalty = problem.cost(&overloaded) - length(&overloaded);
+        assert!((penalty - OVERLOAD_MILES_PER_UNIT).abs() < 1e-9, "{}", penalty);
+        assert!((problem.cost(&fits) - length(&fits)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn late_arrivals_are_charged_by_the_minute() {
+        use crate::schedule::{LatePolicy, Schedule, Speeds};
+        // * At 60 mph every mile takes a minute, so lateness can be read off the distances.
+        let schedule = Schedule {
+            departure: 540.,
+            speeds: Speeds::new(60.),
+            late_policy: LatePolicy::MinimizeLateness,
+        };
+        let strategy = NearestNeighbour;
+        let optimizer = DeliveryOptimizer::new(&strategy).with_schedule(Some(&schedule));
+        // * The first stop can't be served before 10:00; the second was due at departure.
+        let opens = DeliveryRequest {
+            earliest: Some(600.),
+            ..stop("Cake", 0.01, 1)
+        };
+        let due = DeliveryRequest {
+            latest: Some(540.),
+            ..stop("Soup", 0.02, 1)
+        };
+        let deliveries = vec![opens, due];
+        let depot = GeoCoord::from_degrees(34.06, -118.44);
+        let problem = optimizer.problem(&depot, &deliveries);
+        let miles = |a: usize, b: usize| problem.costs().get(a, b);
+        let length = |order: &[usize]| {
+            miles(DEPOT, order[0]) + miles(order[0], order[1]) + miles(order[1], DEPOT)
+        };
+        let penalty = |order: &[usize]| problem.cost(order) - length(order);
+
+        // * Waiting for the cake to be ready makes the soup an hour late and more.
+        let late = 60. + miles(1, 2);
+        assert!((penalty(&[1, 2]) - late * LATENESS_MILES_PER_MINUTE).abs() < 1e-6);
+        let late = miles(DEPOT, 2);
+        assert!((penalty(&[2, 1]) - late * LATENESS_MILES_PER_MINUTE).abs() < 1e-6);
     }
 }
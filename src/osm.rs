@@ -0,0 +1,233 @@
+use crate::binary::invalid;
+use crate::provided::GeoCoord;
+use crate::street_map::{Direction, MapBuilder, StreetMap};
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+
+// What ways without a name tag are called.
+const UNNAMED: &str = "Unnamed road";
+// highway values for roads cars can use.  Footways, tracks, roads under construction and the like
+// aren't among them.
+const DRIVABLE: [&str; 14] = [
+    "motorway",
+    "trunk",
+    "primary",
+    "secondary",
+    "tertiary",
+    "unclassified",
+    "residential",
+    "service",
+    "living_street",
+    "motorway_link",
+    "trunk_link",
+    "primary_link",
+    "secondary_link",
+    "tertiary_link",
+];
+
+pub fn load_osm(path: &str) -> io::Result<StreetMap> {
+    read_osm(BufReader::new(File::open(path)?))
+}
+
+// * Builds a map from an OpenStreetMap XML extract.  Only ways with a drivable highway tag are
+// * kept, bar those closed to motor vehicles, each pair of consecutive nodes becoming a segment
+// * named after the way's name tag.  Ways go one way when their oneway tag says so, or
+// * when OSM implies it: roundabouts and motorways.  Extracts list every node before the ways
+// * that use them, as osmium and the OSM API write them.
+fn read_osm(source: impl BufRead) -> io::Result<StreetMap> {
+    let mut reader = Reader::from_reader(source);
+    let mut buf = Vec::new();
+    let mut nodes: HashMap<i64, GeoCoord> = HashMap::new();
+    // The way being read: its node references and tags.
+    let mut way: Option<(Vec<i64>, HashMap<String, String>)> = None;
+    let mut builder = MapBuilder::default();
+
+    loop {
+        let event = reader.read_event_into(&mut buf).map_err(xml_error)?;
+        match &event {
+            Event::Start(element) | Event::Empty(element) => match element.name().as_ref() {
+                b"node" => {
+                    let (id, lat, lon) = (
+                        attribute(element, b"id")?,
+                        attribute(element, b"lat")?,
+                        attribute(element, b"lon")?,
+                    );
+                    let id = id.parse().map_err(|_| invalid("node id is not a number"))?;
+                    if lat.parse::<f64>().is_err() || lon.parse::<f64>().is_err() {
+                        return Err(invalid("node coordinates are not numbers"));
+                    }
+                    nodes.insert(id, GeoCoord::from(&lat, &lon));
+                }
+                // * A self-closing way has no nodes, and no end tag to finish it.
+                b"way" if matches!(event, Event::Start(_)) => {
+                    way = Some((Vec::new(), HashMap::new()))
+                }
+                b"nd" => {
+                    if let Some((refs, _)) = &mut way {
+                        let id = attribute(element, b"ref")?;
+                        refs.push(id.parse().map_err(|_| invalid("node ref is not a number"))?);
+                    }
+                }
+                b"tag" => {
+                    if let Some((_, tags)) = &mut way {
+                        tags.insert(attribute(element, b"k")?, attribute(element, b"v")?);
+                    }
+                }
+                _ => {}
+            },
+            Event::End(element) if element.name().as_ref() == b"way" => {
+                if let Some((refs, tags)) = way.take() {
+                    add_way(&mut builder, &nodes, &refs, &tags);
+                }
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+        buf.clear();
+    }
+    Ok(builder.finish())
+}
+
+// * Nodes the extract doesn't include, e.g. past the edge of a clipped area, break the way there.
+fn add_way(
+    builder: &mut MapBuilder,
+    nodes: &HashMap<i64, GeoCoord>,
+    refs: &[i64],
+    tags: &HashMap<String, String>,
+) {
+    let Some(highway) = tags.get("highway") else {
+        return;
+    };
+    if !DRIVABLE.contains(&highway.as_str()) {
+        return;
+    }
+    // * motor_vehicle is the more specific tag, so it overrides access.
+    let access = tags.get("motor_vehicle").or_else(|| tags.get("access"));
+    if access.is_some_and(|access| access == "no") {
+        return;
+    }
+    let name = tags.get("name").map_or(UNNAMED, String::as_str);
+    let direction = match tags.get("oneway").map(String::as_str) {
+        Some("yes") | Some("true") | Some("1") => Direction::Forward,
+        Some("-1") | Some("reverse") => Direction::Backward,
+        Some("no") | Some("false") | Some("0") => Direction::Both,
+        _ if tags.get("junction").is_some_and(|junction| junction == "roundabout") => {
+            Direction::Forward
+        }
+        _ if highway == "motorway" => Direction::Forward,
+        _ => Direction::Both,
+    };
+    for pair in refs.windows(2) {
+        if let (Some(start), Some(end)) = (nodes.get(&pair[0]), nodes.get(&pair[1])) {
+            builder.add_segment(start.clone(), end.clone(), name, direction);
+        }
+    }
+}
+
+fn attribute(element: &BytesStart, name: &[u8]) -> io::Result<String> {
+    let attribute = element
+        .try_get_attribute(name)
+        .map_err(xml_error)?
+        .ok_or_else(|| invalid("OSM element is missing an attribute"))?;
+    Ok(attribute.unescape_value().map_err(xml_error)?.into_owned())
+}
+
+fn xml_error(error: impl std::fmt::Display) -> io::Error {
+    invalid(&format!("bad OSM XML: {}", error))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXTRACT: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<osm version="0.6">
+  <node id="1" lat="34.0601" lon="-118.4401"/>
+  <node id="2" lat="34.0602" lon="-118.4402"/>
+  <node id="3" lat="34.0603" lon="-118.4403"/>
+  <node id="4" lat="34.0604" lon="-118.4404"/>
+  <node id="5" lat="34.0605" lon="-118.4405"/>
+  <node id="6" lat="34.0606" lon="-118.4406"/>
+  <way id="10">
+    <nd ref="1"/><nd ref="2"/>
+    <tag k="highway" v="residential"/><tag k="name" v="Main Street"/><tag k="oneway" v="yes"/>
+  </way>
+  <way id="11">
+    <nd ref="2"/><nd ref="3"/>
+    <tag k="highway" v="residential"/><tag k="name" v="Back Street"/><tag k="oneway" v="-1"/>
+  </way>
+  <way id="12">
+    <nd ref="3"/><nd ref="4"/>
+    <tag k="highway" v="service"/>
+  </way>
+  <way id="13">
+    <nd ref="4"/><nd ref="5"/>
+    <tag k="building" v="yes"/><tag k="name" v="Royce Hall"/>
+  </way>
+  <way id="14">
+    <nd ref="4"/><nd ref="99"/><nd ref="5"/><nd ref="6"/>
+    <tag k="highway" v="residential"/><tag k="name" v="Clipped Street"/>
+  </way>
+  <way id="15">
+    <nd ref="1"/><nd ref="6"/>
+    <tag k="highway" v="construction"/><tag k="name" v="Future Street"/>
+  </way>
+  <way id="16">
+    <nd ref="1"/><nd ref="3"/>
+    <tag k="highway" v="footway"/><tag k="name" v="Bruin Walk"/>
+  </way>
+  <way id="17">
+    <nd ref="1"/><nd ref="4"/>
+    <tag k="highway" v="residential"/><tag k="name" v="Gated Street"/><tag k="access" v="no"/>
+  </way>
+  <way id="18">
+    <nd ref="1"/><nd ref="5"/>
+    <tag k="highway" v="service"/><tag k="name" v="Bus Lane"/><tag k="motor_vehicle" v="no"/>
+  </way>
+  <way id="19">
+    <nd ref="2"/><nd ref="4"/>
+    <tag k="highway" v="service"/><tag k="name" v="Delivery Alley"/>
+    <tag k="access" v="no"/><tag k="motor_vehicle" v="yes"/>
+  </way>
+  <way id="20"/>
+  <relation id="30">
+    <member type="way" ref="10" role=""/>
+    <tag k="highway" v="residential"/><tag k="name" v="Phantom Street"/>
+  </relation>
+</osm>"#;
+
+    fn node(id: u32) -> GeoCoord {
+        GeoCoord::from(&format!("34.060{}", id), &format!("-118.440{}", id))
+    }
+
+    // The street traffic takes from one node to the other, if it can drive there directly.
+    fn street(sm: &StreetMap, from: u32, to: u32) -> Option<String> {
+        let mut segments = sm.get_segments_from(&node(from))?;
+        segments.find(|segment| segment.end == node(to)).map(|segment| segment.name)
+    }
+
+    #[test]
+    fn reads_ways_as_streets() {
+        let sm = read_osm(EXTRACT.as_bytes()).unwrap();
+        assert_eq!(street(&sm, 1, 2).as_deref(), Some("Main Street"));
+        assert_eq!(street(&sm, 2, 1), None);
+        assert_eq!(street(&sm, 3, 2).as_deref(), Some("Back Street"));
+        assert_eq!(street(&sm, 2, 3), None);
+        assert_eq!(street(&sm, 3, 4).as_deref(), Some(UNNAMED));
+        assert_eq!(street(&sm, 4, 3).as_deref(), Some(UNNAMED));
+        // The building isn't a street, and the missing node leaves a gap in the clipped way.
+        assert_eq!(street(&sm, 4, 5), None);
+        assert_eq!(street(&sm, 5, 6).as_deref(), Some("Clipped Street"));
+        // * Nor is anything cars can't use, whether by its highway tag or its access tags.
+        for far_end in [6, 3, 4, 5] {
+            assert_eq!(street(&sm, 1, far_end), None);
+        }
+        assert_eq!(street(&sm, 2, 4).as_deref(), Some("Delivery Alley"));
+        // * The self-closing way and the relation make no streets of their own.
+        assert_eq!(sm.segments().count(), 5);
+        assert_eq!(sm.name_count(), 5);
+    }
+}
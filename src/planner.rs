@@ -1,53 +1,415 @@
+use crate::contraction::ContractionHierarchy;
+use crate::distance_matrix::DistanceMatrix;
 use crate::error::{DeliveryFailure, RouteError};
-use crate::optimizer::DeliveryOptimizer;
-use crate::point_router::PointToPointRouter;
+use crate::annealing::{ChainStats, OptimizerConfig, SimulatedAnnealing};
+use crate::optimizer::{reload_points, DeliveryOptimizer, TourOptimizer};
+use crate::point_router::{PointToPointRouter, RouteSearch};
 use crate::provided::*;
-use crate::street_map::StreetMap;
-use std::collections::VecDeque;
+use crate::schedule::{LatePolicy, Schedule, Speeds};
+use crate::street_map::{Snap, StreetMap};
+use std::collections::{HashMap, VecDeque};
+use std::ops::Range;
+
+pub struct DeliveryPlan {
+    // Where the vehicle sets off from, moved onto the street network.
+    pub depot: GeoCoord,
+    pub commands: Vec<DeliveryCommand>,
+    // Every stop in the order it's visited, pickups included.
+    pub order: Vec<DeliveryRequest>,
+    // The drives the commands are made of, in order.
+    pub legs: Vec<Leg>,
+    pub distance: f64,
+    // No order of the same stops can be driven in fewer miles.
+    pub lower_bound: f64,
+    // * How far each stop (depot first, then the others in visiting order) had to move to reach a street.
+    pub snaps: Vec<(String, f64)>,
+    // Deliveries left out because they couldn't make their window.
+    pub rejected: Vec<DeliveryRequest>,
+    // Whether no other order of the stops is shorter.
+    pub optimal: bool,
+    // How each annealing chain went while ordering the stops; empty for other strategies.
+    pub stats: Vec<ChainStats>,
+}
+
+// One drive between stops, and what happens at the end of it.
+#[derive(Clone, Debug)]
+pub struct Leg {
+    pub end: LegEnd,
+    pub distance: f64,
+    // The segments driven, in order; empty if the leg starts where it ends.
+    pub route: Vec<StreetSegment>,
+    // * The leg's share of the plan's commands, ending with the one for what happens on arrival.
+    pub commands: Range<usize>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LegEnd {
+    // The stop at this index of the plan's visiting order.
+    Stop(usize),
+    // Back at the depot to reload.
+    Reload,
+    // Back at the depot, or wherever else the route finishes.
+    Finish,
+}
+
+// * One plan per vehicle, every one of them starting and ending at the same depot.
+pub struct FleetPlan {
+    pub vehicles: Vec<DeliveryPlan>,
+    pub total_distance: f64,
+    pub longest_distance: f64,
+    // * No fleet, however the stops are shared out, can drive fewer miles in total.
+    pub lower_bound: f64,
+    pub rejected: Vec<DeliveryRequest>,
+}
+
+// What a fleet plan tries to keep small.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FleetObjective {
+    // Miles driven by all vehicles together.
+    TotalDistance,
+    // Miles driven by the busiest vehicle, i.e. when the last driver gets back.
+    LongestRoute,
+}
+
+// Where each vehicle's route finishes.
+#[derive(Clone, Debug, PartialEq)]
+pub enum RouteEnd {
+    // Back at the depot it left from.
+    Depot,
+    // At the last stop, e.g. for drivers who go home from there.
+    LastStop,
+    At(GeoCoord),
+}
+
+// Stops moved onto the street network, along with the distances between them.
+struct PreparedStops {
+    depot: GeoCoord,
+    // Every stop to visit, with pickups just ahead of their drop-offs.
+    deliveries: Vec<DeliveryRequest>,
+    // * The optimizer only sees locations, so remember which snap each location came from.
+    snapped: HashMap<GeoCoord, Snap>,
+    // * Several stops can snap to the same point, so each keeps its own snap distance: these,
+    // * and each delivery's snap_distance.
+    depot_snap_distance: f64,
+    end_snap_distance: f64,
+    matrix: DistanceMatrix,
+    rejected: Vec<DeliveryRequest>,
+    // With any end coordinate snapped too.
+    end: RouteEnd,
+}
 
 pub struct DeliveryPlanner<'a> {
+    street_map: &'a StreetMap,
     point_router: PointToPointRouter<'a>,
+    capacity: Option<u32>,
+    schedule: Option<Schedule>,
+    end: RouteEnd,
+    strategy: Box<dyn TourOptimizer>,
 }
 
 impl<'a> DeliveryPlanner<'a> {
     pub fn new(street_map: &'a StreetMap) -> Self {
         DeliveryPlanner {
+            street_map,
             point_router: PointToPointRouter::from(street_map),
+            capacity: None,
+            schedule: None,
+            end: RouteEnd::Depot,
+            strategy: Box::new(SimulatedAnnealing::new(OptimizerConfig::default())),
         }
     }
+    pub fn with_hierarchy(mut self, ch: &'a ContractionHierarchy) -> Self {
+        self.point_router = self.point_router.with_hierarchy(ch);
+        self
+    }
+    // * How directions between stops are searched for, when there's no hierarchy.
+    pub fn with_route_search(mut self, search: RouteSearch) -> Self {
+        self.point_router = self.point_router.with_search(search);
+        self
+    }
+    // * How much each vehicle can carry.  Without one, vehicles never need to reload.
+    pub fn with_capacity(mut self, capacity: u32) -> Self {
+        self.capacity = Some(capacity);
+        self
+    }
+    // * With a schedule, deliveries get ETAs and the optimizer tries to keep to their windows.
+    pub fn with_schedule(mut self, schedule: Schedule) -> Self {
+        self.schedule = Some(schedule);
+        self
+    }
+    // * Where routes finish.  Vehicles still go back to the depot to reload.
+    pub fn with_end(mut self, end: RouteEnd) -> Self {
+        self.end = end;
+        self
+    }
+    // * How to search for a good order of each vehicle's stops.  Simulated annealing by default.
+    pub fn with_strategy(mut self, strategy: Box<dyn TourOptimizer>) -> Self {
+        self.strategy = strategy;
+        self
+    }
     pub fn generate_plan(
         &self,
         depot: GeoCoord,
         deliveries: Vec<DeliveryRequest>,
-    ) -> Result<(Vec<DeliveryCommand>, f64), RouteError> {
-        let mut commands = Vec::<DeliveryCommand>::new();
+    ) -> Result<DeliveryPlan, RouteError> {
+        let stops = self.prepare(depot, deliveries)?;
+        let optimizer = self.optimizer(&stops);
+        let order = optimizer.optimize_order(&stops.depot, stops.deliveries.clone());
+        let mut plan = self.plan_tour(&stops, &order.deliveries)?;
+        plan.lower_bound = optimizer.lower_bound(&stops.depot, &order);
+        plan.rejected = stops.rejected;
+        plan.optimal = order.optimal;
+        plan.stats = order.stats;
+        Ok(plan)
+    }
+    // * Splits the deliveries among several vehicles leaving from the same depot.
+    // * Vehicles that aren't worth sending out get an empty plan.
+    pub fn generate_fleet_plan(
+        &self,
+        depot: GeoCoord,
+        deliveries: Vec<DeliveryRequest>,
+        vehicles: usize,
+        objective: FleetObjective,
+    ) -> Result<FleetPlan, RouteError> {
+        if vehicles == 0 {
+            return Err(RouteError::new(DeliveryFailure::NoVehicles));
+        }
+        let stops = self.prepare(depot, deliveries)?;
+        let optimizer = self.optimizer(&stops);
+        let giant_tour = optimizer.optimize_order(&stops.depot, stops.deliveries.clone());
+        let lower_bound = optimizer.fleet_lower_bound(&stops.depot, &giant_tour);
+        let giant_tour = giant_tour.deliveries;
+
+        let mut plans = Vec::new();
+        for share in split_tour(&optimizer, &stops.depot, &giant_tour, vehicles, objective) {
+            let order = optimizer.optimize_order(&stops.depot, share);
+            let mut plan = self.plan_tour(&stops, &order.deliveries)?;
+            plan.lower_bound = optimizer.lower_bound(&stops.depot, &order);
+            plan.optimal = order.optimal;
+            plan.stats = order.stats;
+            plans.push(plan);
+        }
+        while plans.len() < vehicles {
+            plans.push(self.plan_tour(&stops, &[])?);
+        }
+
+        Ok(FleetPlan {
+            total_distance: plans.iter().map(|plan| plan.distance).sum(),
+            longest_distance: plans.iter().map(|plan| plan.distance).fold(0., f64::max),
+            lower_bound,
+            vehicles: plans,
+            rejected: stops.rejected,
+        })
+    }
+    fn prepare(
+        &self,
+        depot: GeoCoord,
+        deliveries: Vec<DeliveryRequest>,
+    ) -> Result<PreparedStops, RouteError> {
+        if let Some(capacity) = self.capacity {
+            if deliveries.iter().any(|delivery| delivery.demand > capacity) {
+                return Err(RouteError::new(DeliveryFailure::OverCapacity));
+            }
+        }
+        // * Addresses rarely sit on an intersection, so move every stop onto the street network first.
+        let depot_snap = self.snap(&depot)?;
+        let depot_snap_distance = depot_snap.distance;
+        let mut snapped: HashMap<GeoCoord, Snap> = HashMap::new();
+        let depot = depot_snap.location.clone();
+        let mut stops = vec![depot_snap.clone()];
+        snapped.insert(depot.clone(), depot_snap);
+        let mut deliveries = deliveries;
+        // How far each delivery's pickup moved, if it has one.
+        let mut pickup_snap_distances = Vec::with_capacity(deliveries.len());
+        for delivery in &mut deliveries {
+            let snap = self.snap(&delivery.location)?;
+            delivery.location = snap.location.clone();
+            delivery.snap_distance = snap.distance;
+            stops.push(snap.clone());
+            snapped.insert(snap.location.clone(), snap);
+            let mut pickup_snap_distance = 0.;
+            if let Some(pickup) = &delivery.pickup {
+                let snap = self.snap(pickup)?;
+                delivery.pickup = Some(snap.location.clone());
+                pickup_snap_distance = snap.distance;
+                stops.push(snap.clone());
+                snapped.insert(snap.location.clone(), snap);
+            }
+            pickup_snap_distances.push(pickup_snap_distance);
+        }
+        let mut end_snap_distance = 0.;
+        let end = match &self.end {
+            RouteEnd::At(end) => {
+                let snap = self.snap(end)?;
+                let end = snap.location.clone();
+                end_snap_distance = snap.distance;
+                stops.push(snap.clone());
+                snapped.insert(end.clone(), snap);
+                RouteEnd::At(end)
+            }
+            end => end.clone(),
+        };
+
+        // * The optimizer works from real driving distances between every pair of stops.
+        let matrix = DistanceMatrix::compute(&self.point_router, &stops, &self.speeds());
+        if !matrix.all_reachable() {
+            return Err(RouteError::new(DeliveryFailure::NoRoute));
+        }
 
-        let optimizer = DeliveryOptimizer::new(/*self.street_map, &self.point_router*/);
-        let (new_deliveries, _new_crow) = optimizer.optimize_order(&depot, deliveries);
+        let mut deliveries: Vec<(DeliveryRequest, f64)> =
+            deliveries.into_iter().zip(pickup_snap_distances).collect();
+        let mut rejected = Vec::new();
+        if let Some(schedule) = &self.schedule {
+            if schedule.late_policy == LatePolicy::RejectInfeasible {
+                let (on_time, late): (Vec<_>, Vec<_>) =
+                    deliveries.into_iter().partition(|(delivery, _)| {
+                        let drive = match &delivery.pickup {
+                            Some(pickup) => {
+                                matrix.time(&depot, pickup)
+                                    + matrix.time(pickup, &delivery.location)
+                            }
+                            None => matrix.time(&depot, &delivery.location),
+                        };
+                        delivery
+                            .latest
+                            .is_none_or(|latest| schedule.departure + drive <= latest)
+                    });
+                deliveries = on_time;
+                rejected = late.into_iter().map(|(delivery, _)| delivery).collect();
+            }
+        }
+        let deliveries = deliveries
+            .into_iter()
+            .flat_map(|(delivery, pickup_snap_distance)| {
+                let pickup = delivery.pickup_stop().map(|pickup| DeliveryRequest {
+                    snap_distance: pickup_snap_distance,
+                    ..pickup
+                });
+                pickup.into_iter().chain([delivery])
+            })
+            .collect();
+        Ok(PreparedStops {
+            depot,
+            deliveries,
+            snapped,
+            depot_snap_distance,
+            end_snap_distance,
+            matrix,
+            rejected,
+            end,
+        })
+    }
+    fn optimizer<'s>(&'s self, stops: &'s PreparedStops) -> DeliveryOptimizer<'s> {
+        DeliveryOptimizer::new(self.strategy.as_ref())
+            .with_matrix(&stops.matrix)
+            .with_capacity(self.capacity)
+            .with_schedule(self.schedule.as_ref())
+            .with_end(stops.end.clone())
+    }
+    fn speeds(&self) -> Speeds {
+        self.schedule
+            .as_ref()
+            .map_or_else(Speeds::default, |schedule| schedule.speeds.clone())
+    }
+    // Routes a single vehicle through deliveries in the given order and on to where it finishes,
+    // reloading at the depot on the way whenever it runs out of room.
+    fn plan_tour(
+        &self,
+        stops: &PreparedStops,
+        deliveries: &[DeliveryRequest],
+    ) -> Result<DeliveryPlan, RouteError> {
+        let mut commands = Vec::<DeliveryCommand>::new();
+        let snapped = &stops.snapped;
+        let mut snaps = vec![("Depot".to_string(), stops.depot_snap_distance)];
 
-        let mut current = depot.clone();
+        let depot = &snapped[&stops.depot];
+        let mut current = depot;
         let mut routes: Vec<Vec<StreetSegment>> = Vec::new();
+        // One per route, except an empty last route that goes nowhere.
+        let mut legs: Vec<Leg> = Vec::new();
+        let leg = |end: LegEnd, distance: f64, route: &[StreetSegment]| Leg {
+            end,
+            distance,
+            route: route.to_vec(),
+            commands: 0..0,
+        };
+        // What happens at the end of each route but the last.
+        let mut arrivals: Vec<DeliveryCommand> = Vec::new();
         let mut total_distance = 0.;
 
-        for delivery in &new_deliveries {
-            let (route, cost) = self
-                .point_router
-                .generate_route(&current, &delivery.location)?;
-            current = delivery.location.clone();
+        // Only tracked with a schedule, in minutes after midnight.
+        let mut clock = self.schedule.as_ref().map(|schedule| schedule.departure);
+        let speeds = self.speeds();
+        let drive = |clock: &mut Option<f64>, route: &[StreetSegment]| {
+            if let Some(time) = clock {
+                *time += route.iter().map(|seg| speeds.minutes(seg)).sum::<f64>();
+            }
+        };
+
+        let reloads = reload_points(deliveries, self.capacity);
+        for (i, delivery) in deliveries.iter().enumerate() {
+            if reloads.contains(&i) {
+                let (route, cost) = self.point_router.route_between(current, depot)?;
+                drive(&mut clock, &route);
+                current = depot;
+                legs.push(leg(LegEnd::Reload, cost, &route));
+                routes.push(route);
+                arrivals.push(DeliveryCommand::new_reload());
+                total_distance += cost;
+            }
+            let next = &snapped[&delivery.location];
+            let (route, cost) = self.point_router.route_between(current, next)?;
+            drive(&mut clock, &route);
+            current = next;
+            legs.push(leg(LegEnd::Stop(i), cost, &route));
             routes.push(route);
+            let mut deliver = match delivery.kind {
+                StopKind::Dropoff => {
+                    snaps.push((delivery.item.clone(), delivery.snap_distance));
+                    DeliveryCommand::new_deliver(delivery.item.clone())
+                }
+                StopKind::Pickup => {
+                    let stop = format!("Pickup for {}", delivery.item);
+                    snaps.push((stop, delivery.snap_distance));
+                    DeliveryCommand::new_pickup(delivery.item.clone())
+                }
+            };
+            if let Some(time) = &mut clock {
+                // Arriving early means waiting for the window to open.
+                *time = time.max(delivery.earliest.unwrap_or(*time));
+                deliver.set_eta(*time);
+            }
+            arrivals.push(deliver);
             total_distance += cost;
         }
-        let (route, cost) = self.point_router.generate_route(&current, &depot)?;
-        routes.push(route);
-        total_distance += cost;
+        let finish = match &stops.end {
+            RouteEnd::Depot => Some(depot),
+            RouteEnd::LastStop => None,
+            RouteEnd::At(end) => {
+                snaps.push(("Route end".to_string(), stops.end_snap_distance));
+                Some(&snapped[end])
+            }
+        };
+        // * A vehicle with nothing to deliver never leaves.
+        match finish.filter(|_| !deliveries.is_empty()) {
+            Some(finish) => {
+                let (route, cost) = self.point_router.route_between(current, finish)?;
+                legs.push(leg(LegEnd::Finish, cost, &route));
+                routes.push(route);
+                total_distance += cost;
+            }
+            // * Nothing left to drive, but the last route still closes off the directions.
+            None => routes.push(Vec::new()),
+        }
 
         // The constructions are different.
         let mut here;
         let mut last = StreetSegment::new();
-        let delivery_count = new_deliveries.len();
-        let mut delivered: usize = 0;
+        let stop_count = arrivals.len();
+        let mut visited: usize = 0;
 
-        for route in &routes {
+        for (i, route) in routes.iter().enumerate() {
+            let first_command = commands.len();
             let mut moves = VecDeque::<DeliveryCommand>::new();
             for segment in route {
                 here = segment.clone();
@@ -72,7 +434,7 @@ impl<'a> DeliveryPlanner<'a> {
                     commands.push(moves.pop_front().unwrap());
                     let direction = angle_between_2_lines(&last, &here);
 
-                    if direction < 1. || direction > 359. {
+                    if !(1.0..=359.0).contains(&direction) {
                         moves.push_back(DeliveryCommand::new_proceed(
                             proceed_dir(direction).to_string(),
                             here.name.clone(),
@@ -85,47 +447,147 @@ impl<'a> DeliveryPlanner<'a> {
                             turn_dir(direction).to_string(),
                             here.name.clone(),
                         ));
+                        // * The turn is followed by driving down the new street, however short.
+                        moves.push_back(DeliveryCommand::new_proceed(
+                            proceed_dir(angle_of_line(&here)).to_string(),
+                            here.name.clone(),
+                            here.length(),
+                        ));
                         last = here;
                     }
                 }
             }
-            if delivered != delivery_count {
+            if visited != stop_count {
                 // * Push in the final proceed command.
                 if !moves.is_empty() {
                     commands.push(moves.front().unwrap().clone());
                 }
-                commands.push(DeliveryCommand::new_deliver(
-                    new_deliveries[delivered].item.clone(),
-                ));
-                delivered += 1;
-            } else if delivered == delivery_count {
+                commands.push(arrivals[visited].clone());
+                visited += 1;
+                legs[i].commands = first_command..commands.len();
+            } else if visited == stop_count {
                 if !moves.is_empty() {
                     commands.push(moves.front().unwrap().clone());
                 }
-                return Ok((commands, total_distance));
+                if let Some(leg) = legs.get_mut(i) {
+                    leg.commands = first_command..commands.len();
+                }
+                return Ok(DeliveryPlan {
+                    depot: stops.depot.clone(),
+                    commands,
+                    order: deliveries.to_vec(),
+                    legs,
+                    distance: total_distance,
+                    lower_bound: 0.,
+                    snaps,
+                    rejected: Vec::new(),
+                    optimal: false,
+                    stats: Vec::new(),
+                });
             }
         }
         Err(RouteError::new(DeliveryFailure::NoRoute))
     }
+    fn snap(&self, gc: &GeoCoord) -> Result<Snap, RouteError> {
+        self.street_map
+            .snap(gc)
+            .ok_or_else(|| RouteError::new(DeliveryFailure::BadCoord))
+    }
+}
+
+// * Route first, cluster second: cut one good tour of every delivery into consecutive runs,
+// * one per vehicle, choosing the cuts that best serve the objective.
+fn split_tour(
+    optimizer: &DeliveryOptimizer,
+    depot: &GeoCoord,
+    tour: &[DeliveryRequest],
+    vehicles: usize,
+    objective: FleetObjective,
+) -> Vec<Vec<DeliveryRequest>> {
+    if tour.is_empty() {
+        return Vec::new();
+    }
+    // run_costs[i][j] is one vehicle's tour through tour[i..j], reloads included.
+    let run_costs = optimizer.run_costs(depot, tour);
+    best_cuts(tour.len(), vehicles, objective, |i, j| run_costs[i][j])
+        .into_iter()
+        .map(|run| tour[run].to_vec())
+        .collect()
+}
+
+// * Dynamic programming over (vehicles used, deliveries covered) finds the best cuts of n
+// * deliveries into at most `vehicles` runs exactly, given what each run i..j costs.  A run that
+// * can't be driven, e.g. one holding a drop-off without its pickup, costs infinity, so the cuts
+// * never separate a pickup from its drop-off.
+fn best_cuts(
+    n: usize,
+    vehicles: usize,
+    objective: FleetObjective,
+    run_cost: impl Fn(usize, usize) -> f64,
+) -> Vec<Range<usize>> {
+    // Scores compare the objective first and the other measure as a tie-break.
+    let score = |total: f64, longest: f64| match objective {
+        FleetObjective::TotalDistance => (total, longest),
+        FleetObjective::LongestRoute => (longest, total),
+    };
+    let better = |a: (f64, f64), b: (f64, f64)| a.0 < b.0 || (a.0 == b.0 && a.1 < b.1);
+
+    // best[k][j]: (total, longest, where the last run started) covering the first j deliveries with k runs.
+    let mut best: Vec<Vec<Option<(f64, f64, usize)>>> = vec![vec![None; n + 1]; vehicles + 1];
+    best[0][0] = Some((0., 0., 0));
+    for k in 1..=vehicles {
+        for j in k..=n {
+            for i in (k - 1)..j {
+                if let Some((total, longest, _)) = best[k - 1][i] {
+                    let run = run_cost(i, j);
+                    let candidate = (total + run, longest.max(run), i);
+                    let improves = best[k][j].is_none_or(|(t, l, _)| {
+                        better(score(candidate.0, candidate.1), score(t, l))
+                    });
+                    if improves {
+                        best[k][j] = Some(candidate);
+                    }
+                }
+            }
+        }
+    }
+
+    let mut runs_used = 1;
+    for k in 2..=vehicles {
+        if let (Some((t, l, _)), Some((bt, bl, _))) = (best[k][n], best[runs_used][n]) {
+            if better(score(t, l), score(bt, bl)) {
+                runs_used = k;
+            }
+        }
+    }
+    let mut runs = Vec::new();
+    let mut end = n;
+    for k in (1..=runs_used).rev() {
+        let start = best[k][end].unwrap().2;
+        runs.push(start..end);
+        end = start;
+    }
+    runs.reverse();
+    runs
 }
 
 // * Do we need to modulo the direction angle?
 fn proceed_dir(direction: f64) -> &'static str {
-    if direction >= 0.0 && direction < 22.5 {
+    if (0.0..22.5).contains(&direction) {
         "east"
-    } else if direction >= 22.5 && direction < 67.5 {
+    } else if (22.5..67.5).contains(&direction) {
         "northeast"
-    } else if direction >= 67.5 && direction < 112.5 {
+    } else if (67.5..112.5).contains(&direction) {
         "north"
-    } else if direction >= 112.5 && direction < 157.5 {
+    } else if (112.5..157.5).contains(&direction) {
         "northwest"
-    } else if direction >= 157.5 && direction < 202.5 {
+    } else if (157.5..202.5).contains(&direction) {
         "west"
-    } else if direction >= 202.5 && direction < 247.5 {
+    } else if (202.5..247.5).contains(&direction) {
         "southwest"
-    } else if direction > 247.5 && direction < 292.5 {
+    } else if (247.5..292.5).contains(&direction) {
         "south"
-    } else if direction >= 292.5 && direction < 337.5 {
+    } else if (292.5..337.5).contains(&direction) {
         "southeast"
     } else {
         "east"
@@ -141,3 +603,115 @@ fn turn_dir(direction: f64) -> &'static str {
         ""
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // * Four deliveries: one vehicle can drive them all for 7, or two can take half each for 4.
+    // * Any other run costs more than either.
+    fn run_cost(i: usize, j: usize) -> f64 {
+        match (i, j) {
+            (0, 4) => 7.,
+            (0, 2) | (2, 4) => 4.,
+            _ => 4.5,
+        }
+    }
+
+    // The runs four deliveries are cut into, as where each starts and ends.
+    fn cuts(
+        vehicles: usize,
+        objective: FleetObjective,
+        run_cost: impl Fn(usize, usize) -> f64,
+    ) -> Vec<(usize, usize)> {
+        let runs = best_cuts(4, vehicles, objective, run_cost);
+        runs.into_iter().map(|run| (run.start, run.end)).collect()
+    }
+
+    #[test]
+    fn cuts_serve_the_objective() {
+        let total = FleetObjective::TotalDistance;
+        let longest = FleetObjective::LongestRoute;
+        assert_eq!(cuts(1, total, run_cost), [(0, 4)]);
+        assert_eq!(cuts(1, longest, run_cost), [(0, 4)]);
+        // * One vehicle drives 7 in all; two drive 8 between them but neither more than 4.
+        assert_eq!(cuts(3, total, run_cost), [(0, 4)]);
+        assert_eq!(cuts(3, longest, run_cost), [(0, 2), (2, 4)]);
+    }
+
+    #[test]
+    fn cuts_never_split_a_pair() {
+        // * Pickups at 0 and 2, their drop-offs at 1 and 3: a run starting at a drop-off would
+        // * leave its pickup with another vehicle, so it can't be driven.  Otherwise every
+        // * delivery on its own would be the cheapest.
+        let paired = |i: usize, j: usize| {
+            if i % 2 == 1 {
+                f64::INFINITY
+            } else {
+                (j - i) as f64 * (j - i) as f64
+            }
+        };
+        for objective in [FleetObjective::TotalDistance, FleetObjective::LongestRoute] {
+            assert_eq!(cuts(4, objective, paired), [(0, 2), (2, 4)]);
+        }
+    }
+
+    #[test]
+    fn deliveries_bigger_than_a_vehicle_are_refused() {
+        let map = "Main Street\n1\n34.0600 -118.4400 34.0600 -118.4390\n";
+        let sm = StreetMap::read_text(map.as_bytes());
+        let depot = GeoCoord::from("34.0600", "-118.4400");
+        let sofa = DeliveryRequest {
+            demand: 4,
+            ..DeliveryRequest::from("Sofa", &GeoCoord::from("34.0600", "-118.4390"))
+        };
+        let planner = DeliveryPlanner::new(&sm).with_capacity(3);
+        let error = planner.generate_plan(depot.clone(), vec![sofa.clone()]).err().unwrap();
+        assert!(matches!(error.kind(), DeliveryFailure::OverCapacity));
+        let planner = DeliveryPlanner::new(&sm).with_capacity(4);
+        assert!(planner.generate_plan(depot, vec![sofa]).is_ok());
+    }
+
+    #[test]
+    fn late_deliveries_follow_the_policy() {
+        let map = "Main Street\n2\n34.0600 -118.4400 34.0600 -118.4300\n\
+                   34.0600 -118.4300 34.0600 -118.4200\n";
+        let sm = StreetMap::read_text(map.as_bytes());
+        let depot = GeoCoord::from("34.0600", "-118.4400");
+        let end = GeoCoord::from("34.0600", "-118.4200");
+        // * The far end of the street is over a minute's drive, but the soup is due in one.
+        let soup = DeliveryRequest {
+            latest: Some(541.),
+            ..DeliveryRequest::from("Soup", &end)
+        };
+        let bread = DeliveryRequest::from("Bread", &GeoCoord::from("34.0600", "-118.4300"));
+        let drive = distance_earth_miles(&depot, &end) * 2.;
+        let plan = |late_policy| {
+            let schedule = Schedule {
+                departure: 540.,
+                speeds: Speeds::new(30.),
+                late_policy,
+            };
+            let planner = DeliveryPlanner::new(&sm).with_schedule(schedule);
+            planner.generate_plan(depot.clone(), vec![soup.clone(), bread.clone()]).unwrap()
+        };
+        let eta = |plan: &DeliveryPlan, item: &str| {
+            let command = plan.commands.iter().find(|command| command.item() == item);
+            command.and_then(|command| command.eta())
+        };
+
+        let plan_late = plan(LatePolicy::MinimizeLateness);
+        assert!(plan_late.rejected.is_empty());
+        // * Whichever comes first, the soup can't arrive before the drive down the whole street.
+        let soup_eta = eta(&plan_late, "Soup").unwrap();
+        assert!(soup_eta >= 540. + drive - 1e-3, "{}", soup_eta);
+        assert!(eta(&plan_late, "Bread").is_some());
+
+        let plan_strict = plan(LatePolicy::RejectInfeasible);
+        let rejected: Vec<&str> = plan_strict.rejected.iter().map(|r| r.item.as_str()).collect();
+        assert_eq!(rejected, ["Soup"]);
+        assert_eq!(eta(&plan_strict, "Soup"), None);
+        let bread_eta = eta(&plan_strict, "Bread").unwrap();
+        assert!((bread_eta - (540. + drive / 2.)).abs() < 1e-3, "{}", bread_eta);
+    }
+}
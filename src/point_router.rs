@@ -1,119 +1,700 @@
 #![allow(dead_code, unused_variables)]
+use crate::contraction::ContractionHierarchy;
 use crate::error::{DeliveryFailure, RouteError};
 use crate::provided::{self, GeoCoord, StreetSegment};
-use crate::street_map::StreetMap;
+use crate::schedule::Speeds;
+use crate::street_map::{Edge, NameId, NodeId, Snap, StreetMap};
 use ordered_float::OrderedFloat;
 use std::cmp::Ordering;
 use std::collections::{BinaryHeap, HashMap};
 
 // Not sure if deriving PartialEq and Eq is okay here.
 #[derive(Clone, Copy, Debug)]
-struct Node<'a> {
-    coord: &'a GeoCoord,
+struct Node {
+    id: NodeId,
     cost: OrderedFloat<f64>,
 }
-// With this lifetime, impossible to create empty Node.
-impl<'a> Node<'a> {
-    pub fn from(coord: &GeoCoord, f_cost: f64) -> Node {
+impl Node {
+    pub fn from(id: NodeId, f_cost: f64) -> Node {
         Node {
-            coord,
+            id,
             cost: OrderedFloat::from(f_cost),
         }
     }
 }
-impl PartialEq for Node<'_> {
+impl PartialEq for Node {
     fn eq(&self, other: &Self) -> bool {
-        self.coord == other.coord
+        self.id == other.id
     }
 }
-impl Eq for Node<'_> {}
-impl Ord for Node<'_> {
+impl Eq for Node {}
+impl Ord for Node {
     fn cmp(&self, other: &Self) -> Ordering {
         other.cost.cmp(&self.cost)
     }
 }
-impl PartialOrd for Node<'_> {
+impl PartialOrd for Node {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
         Some(self.cmp(other))
     }
 }
 
+// How routes are searched for when there's no hierarchy to ask.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RouteSearch {
+    // A* from the start towards the end.
+    AStar,
+    // A* from both ends at once, meeting in the middle.
+    Bidirectional,
+}
+
 // Lifetimes: You have a value, and a reference to a value.
 // The reference must be destroyed before the value is.
 // To ensure at compile time the reference is destroyed first,
 // you specify its lifetime in code.
 pub struct PointToPointRouter<'a> {
     street_map: &'a StreetMap,
+    hierarchy: Option<&'a ContractionHierarchy>,
+    search: RouteSearch,
 }
 
 impl<'a> PointToPointRouter<'a> {
-    pub fn from(sm: &StreetMap) -> PointToPointRouter {
-        PointToPointRouter { street_map: &sm }
+    pub fn from(sm: &StreetMap) -> PointToPointRouter<'_> {
+        PointToPointRouter {
+            street_map: sm,
+            hierarchy: None,
+            search: RouteSearch::AStar,
+        }
+    }
+    // * Answers queries from a preprocessed hierarchy instead of searching the map each time.
+    pub fn with_hierarchy(mut self, ch: &'a ContractionHierarchy) -> Self {
+        self.hierarchy = Some(ch);
+        self
+    }
+    // * Only used without a hierarchy.
+    pub fn with_search(mut self, search: RouteSearch) -> Self {
+        self.search = search;
+        self
     }
     pub fn generate_route(
         &self,
         start: &GeoCoord,
         end: &GeoCoord,
     ) -> Result<(Vec<StreetSegment>, f64), RouteError> {
-        if self.street_map.get_segments_from(start) == None
-            || self.street_map.get_segments_from(end) == None
+        self.route_between(&Snap::at(start), &Snap::at(end))
+    }
+    // * Routes between snapped points, which may lie partway along a segment.
+    // * Such points become temporary nodes joined to the ends of their segment by partial segments.
+    pub fn route_between(
+        &self,
+        from: &Snap,
+        to: &Snap,
+    ) -> Result<(Vec<StreetSegment>, f64), RouteError> {
+        let start = &from.location;
+        let end = &to.location;
+        if (from.segment.is_none() && self.street_map.node(start).is_none())
+            || (to.segment.is_none() && self.street_map.node(end).is_none())
         {
             return Err(RouteError::new(DeliveryFailure::BadCoord));
         }
+        if start == end {
+            return Ok((Vec::new(), 0.));
+        }
+        if let Some(ch) = self.hierarchy {
+            return self.route_with_hierarchy(ch, from, to);
+        }
+
+        // * Mid-block points join the network through partial segments, kept out of the map itself.
+        let mut graph = RouteGraph::new(self.street_map);
+        for seg in self
+            .departures(from)
+            .into_iter()
+            .chain(self.arrivals(to))
+            .chain(self.along_segment(from, to))
+        {
+            graph.add(&seg);
+        }
+        let (start, end) = (graph.node(start), graph.node(end));
+        let route = match self.search {
+            RouteSearch::AStar => a_star(&graph, start, end),
+            RouteSearch::Bidirectional => bidirectional_a_star(&graph, start, end),
+        };
+        route.ok_or_else(|| RouteError::new(DeliveryFailure::NoRoute))
+    }
+    // The hierarchy only knows real intersections, so mid-block points start from (or finish at)
+    // the ends of their segment, with the partial segment's length as a head start.
+    fn route_with_hierarchy(
+        &self,
+        ch: &ContractionHierarchy,
+        from: &Snap,
+        to: &Snap,
+    ) -> Result<(Vec<StreetSegment>, f64), RouteError> {
+        if from.segment.is_none() && to.segment.is_none() {
+            return ch.query(&from.location, &to.location);
+        }
+        let departures = self.departures(from);
+        let arrivals = self.arrivals(to);
+        let sources: Vec<(&GeoCoord, f64)> = if from.segment.is_some() {
+            departures.iter().map(|seg| (&seg.end, seg.length())).collect()
+        } else {
+            vec![(&from.location, 0.)]
+        };
+        let targets: Vec<(&GeoCoord, f64)> = if to.segment.is_some() {
+            arrivals.iter().map(|seg| (&seg.start, seg.length())).collect()
+        } else {
+            vec![(&to.location, 0.)]
+        };
 
-        // need map and priority queue
-        let mut prevs: HashMap<GeoCoord, GeoCoord> = HashMap::new();
-        let mut g_costs: HashMap<GeoCoord, f64> = HashMap::new();
-        // Nodes are used solely to compare f-costs in the priority queue.
+        let mut best = self
+            .along_segment(from, to)
+            .map(|seg| (seg.length(), vec![seg]));
+        match ch.query_seeded(&sources, &targets) {
+            Ok((source, target, core, distance)) => {
+                if best.as_ref().is_none_or(|(d, _)| distance < *d) {
+                    let mut route = Vec::new();
+                    route.extend(departures.get(source).cloned());
+                    route.extend(core);
+                    route.extend(arrivals.get(target).cloned());
+                    best = Some((distance, route));
+                }
+            }
+            Err(e) => {
+                if best.is_none() {
+                    return Err(e);
+                }
+            }
+        }
+        let (distance, route) = best.unwrap();
+        Ok((route, distance))
+    }
+    // * One-to-many Dijkstra: the driving distance from one stop to each of the others, and the
+    // * minutes that drive takes at the given speeds.  Stops that can't be reached come back as infinity.
+    pub fn distances_from(&self, from: &Snap, to: &[Snap], speeds: &Speeds) -> Vec<(f64, f64)> {
+        let sm = self.street_map;
+        let mut distances = vec![(f64::INFINITY, f64::INFINITY); to.len()];
+        // Nodes where the search can finish at a stop, with the partial segment still to drive.
+        let mut finishes: HashMap<NodeId, Vec<(usize, StreetSegment)>> = HashMap::new();
+        for (i, stop) in to.iter().enumerate() {
+            if stop.location == from.location {
+                distances[i] = (0., 0.);
+                continue;
+            }
+            if let Some(seg) = self.along_segment(from, stop) {
+                distances[i] = (seg.length(), speeds.minutes(&seg));
+            }
+            if stop.segment.is_some() {
+                for seg in self.arrivals(stop) {
+                    finishes.entry(sm.node(&seg.start).unwrap()).or_default().push((i, seg));
+                }
+            } else if let Some(node) = sm.node(&stop.location) {
+                let stay = StreetSegment::from(&stop.location, &stop.location, "");
+                finishes.entry(node).or_default().push((i, stay));
+            }
+        }
+
+        // Looked up once per street, not once per segment.
+        let mph: Vec<f64> = (0..sm.name_count() as NameId)
+            .map(|name| speeds.mph(sm.name(name)))
+            .collect();
+        let mut dist = vec![f64::INFINITY; sm.node_count()];
+        // Minutes along the shortest path found so far, not the quickest path.
+        let mut minutes = vec![f64::INFINITY; sm.node_count()];
         let mut nodes: BinaryHeap<Node> = BinaryHeap::new();
+        if from.segment.is_some() {
+            for seg in self.departures(from) {
+                let node = sm.node(&seg.end).unwrap();
+                dist[node as usize] = seg.length();
+                minutes[node as usize] = speeds.minutes(&seg);
+                nodes.push(Node::from(node, seg.length()));
+            }
+        } else if let Some(node) = sm.node(&from.location) {
+            dist[node as usize] = 0.;
+            minutes[node as usize] = 0.;
+            nodes.push(Node::from(node, 0.));
+        }
 
-        let start_node = Node::from(start, 0.);
-        prevs.insert(start.clone(), start.clone());
-        g_costs.insert(start.clone(), 0.);
-        nodes.push(start_node.clone());
-
-        // FIXME: The g_costs are going wack.  Discover what inflates them so.
-        while !nodes.is_empty() {
-            let current = nodes.pop().unwrap();
-
-            if current.coord == end {
-                let mut current: GeoCoord = current.coord.clone();
-                let mut route = Vec::new();
-                let mut total_distance = 0.;
-                while &current != start {
-                    let segs = self.street_map.get_segments_from(&current).unwrap();
-                    let trace = prevs.get(&current).unwrap();
-                    if let Some(seg) = segs.iter().find(|&seg| &seg.end == trace) {
-                        current = seg.end.clone();
-                        route.push(seg.reverse_segment());
-                        total_distance += seg.length();
+        let mut remaining = finishes.len();
+        while let Some(current) = nodes.pop() {
+            let d = current.cost.into_inner();
+            if d > dist[current.id as usize] {
+                continue;
+            }
+            let t = minutes[current.id as usize];
+            if let Some(stops) = finishes.get(&current.id) {
+                for (i, seg) in stops {
+                    if d + seg.length() < distances[*i].0 {
+                        distances[*i] = (d + seg.length(), t + speeds.minutes(seg));
                     }
                 }
-                route.reverse();
-                // ! FIXME: g_costs has wack values in its map.
-                // let total_distance = g_costs.get(end).unwrap().clone();
-                return Ok((route, total_distance));
-            }
-            // Remember: g-cost is distance from start to node.
-            // h-cost is distance from node to end. f-cost is g + h.
-            if let Some(segs) = self.street_map.get_segments_from(current.coord) {
-                for seg in segs {
-                    let new_gcost = current.cost.into_inner()
-                        + provided::distance_earth_miles(current.coord, &seg.end);
-                    // Not finding seg.end means it is an untravelled node.
-                    if !g_costs.contains_key(&seg.end)
-                        || new_gcost < g_costs.get(&seg.end).unwrap().clone()
-                    {
-                        g_costs.insert(seg.end.clone(), new_gcost);
-                        let f = new_gcost + provided::distance_earth_miles(&seg.end, end);
-                        nodes.push(Node::from(&seg.end, f));
-                        prevs.insert(seg.end.clone(), current.coord.clone());
+                remaining -= 1;
+                if remaining == 0 {
+                    break;
+                }
+            }
+            for edge in sm.edges_from(current.id) {
+                let new_dist = d + edge.length;
+                if new_dist < dist[edge.to as usize] {
+                    dist[edge.to as usize] = new_dist;
+                    minutes[edge.to as usize] = t + edge.length / mph[edge.name as usize] * 60.;
+                    nodes.push(Node::from(edge.to, new_dist));
+                }
+            }
+        }
+        distances
+    }
+    // Partial segments from a mid-block point to the ends of its segment that traffic allows.
+    fn departures(&self, from: &Snap) -> Vec<StreetSegment> {
+        let mut segs = Vec::new();
+        if let Some(seg) = &from.segment {
+            for (a, b) in [(&seg.start, &seg.end), (&seg.end, &seg.start)] {
+                if self.can_travel(a, b) {
+                    segs.push(StreetSegment::from(&from.location, b, &seg.name));
+                }
+            }
+        }
+        segs
+    }
+    // Partial segments from the ends of a segment into a mid-block point on it.
+    fn arrivals(&self, to: &Snap) -> Vec<StreetSegment> {
+        let mut segs = Vec::new();
+        if let Some(seg) = &to.segment {
+            for (a, b) in [(&seg.start, &seg.end), (&seg.end, &seg.start)] {
+                if self.can_travel(a, b) {
+                    segs.push(StreetSegment::from(a, &to.location, &seg.name));
+                }
+            }
+        }
+        segs
+    }
+    // The direct drive between two points on the same segment, if traffic allows it.
+    fn along_segment(&self, from: &Snap, to: &Snap) -> Option<StreetSegment> {
+        let (seg_from, seg_to) = (from.segment.as_ref()?, to.segment.as_ref()?);
+        if seg_from != seg_to && seg_from != &seg_to.reverse_segment() {
+            return None;
+        }
+        // * Heading towards whichever end to is closer to than from is.
+        let (a, b) = (&seg_from.start, &seg_from.end);
+        let forward = provided::distance_earth_miles(a, &from.location)
+            < provided::distance_earth_miles(a, &to.location);
+        let legal = if forward {
+            self.can_travel(a, b)
+        } else {
+            self.can_travel(b, a)
+        };
+        if legal {
+            Some(StreetSegment::from(&from.location, &to.location, &seg_from.name))
+        } else {
+            None
+        }
+    }
+    fn can_travel(&self, a: &GeoCoord, b: &GeoCoord) -> bool {
+        let sm = self.street_map;
+        match (sm.node(a), sm.node(b)) {
+            (Some(a), Some(b)) => sm.edges_from(a).iter().any(|edge| edge.to == b),
+            _ => false,
+        }
+    }
+}
+
+// * The street map, plus the mid-block points one route starts or ends at.  Those are numbered on
+// * from the map's own nodes, and joined to it by partial segments.
+struct RouteGraph<'g> {
+    street_map: &'g StreetMap,
+    extra_coords: Vec<GeoCoord>,
+    // Partial segments, as (from, edge).
+    extra_edges: Vec<(NodeId, Edge)>,
+}
+
+impl<'g> RouteGraph<'g> {
+    fn new(street_map: &'g StreetMap) -> RouteGraph<'g> {
+        RouteGraph {
+            street_map,
+            extra_coords: Vec::new(),
+            extra_edges: Vec::new(),
+        }
+    }
+    fn node_count(&self) -> usize {
+        self.street_map.node_count() + self.extra_coords.len()
+    }
+    // The node at gc, numbering it if it's a new mid-block point.
+    fn node(&mut self, gc: &GeoCoord) -> NodeId {
+        if let Some(node) = self.street_map.node(gc) {
+            return node;
+        }
+        let map_nodes = self.street_map.node_count();
+        match self.extra_coords.iter().position(|extra| extra == gc) {
+            Some(i) => (map_nodes + i) as NodeId,
+            None => {
+                self.extra_coords.push(gc.clone());
+                (map_nodes + self.extra_coords.len() - 1) as NodeId
+            }
+        }
+    }
+    fn add(&mut self, seg: &StreetSegment) {
+        let (from, to) = (self.node(&seg.start), self.node(&seg.end));
+        let name = self
+            .street_map
+            .name_id(&seg.name)
+            .expect("Partial segment of a street that isn't on the map.");
+        let length = seg.length();
+        self.extra_edges.push((from, Edge { to, name, length }));
+    }
+    fn coord(&self, node: NodeId) -> &GeoCoord {
+        match (node as usize).checked_sub(self.street_map.node_count()) {
+            Some(i) => &self.extra_coords[i],
+            None => self.street_map.coord(node),
+        }
+    }
+    fn is_map_node(&self, node: NodeId) -> bool {
+        (node as usize) < self.street_map.node_count()
+    }
+    fn leaving(&self, node: NodeId) -> impl Iterator<Item = Edge> + '_ {
+        let map_edges = if self.is_map_node(node) {
+            self.street_map.edges_from(node)
+        } else {
+            &[]
+        };
+        let extra_edges = self.extra_edges.iter().filter(move |(from, _)| *from == node);
+        map_edges.iter().copied().chain(extra_edges.map(|&(_, edge)| edge))
+    }
+    // Edges into node, with `to` naming where they come from.
+    fn arriving(&self, node: NodeId) -> impl Iterator<Item = Edge> + '_ {
+        let map_edges = if self.is_map_node(node) {
+            self.street_map.edges_into(node)
+        } else {
+            &[]
+        };
+        let extra_edges = self.extra_edges.iter().filter(move |(_, edge)| edge.to == node);
+        map_edges
+            .iter()
+            .copied()
+            .chain(extra_edges.map(|&(from, edge)| Edge { to: from, ..edge }))
+    }
+    fn segment(&self, from: NodeId, to: NodeId, name: NameId) -> StreetSegment {
+        StreetSegment::from(self.coord(from), self.coord(to), self.street_map.name(name))
+    }
+}
+
+// * A*, with a closed set.  Straight-line distance never overestimates a drive, and never drops by
+// * more than a segment's length along that segment, so the first time a node comes off the queue
+// * its g-cost is final and it never needs looking at again.
+fn a_star(graph: &RouteGraph, start: NodeId, end: NodeId) -> Option<(Vec<StreetSegment>, f64)> {
+    let end_coord = graph.coord(end);
+    // Remember: g-cost is distance from start to node.
+    // h-cost is distance from node to end. f-cost is g + h.
+    let h_cost = |node: NodeId| provided::distance_earth_miles(graph.coord(node), end_coord);
+    let mut search = Frontier::new(graph.node_count(), start, h_cost(start));
+    while let Some((current, g_cost)) = search.settle() {
+        if current == end {
+            return Some((search.route_back(graph, start, end), g_cost));
+        }
+        for edge in graph.leaving(current) {
+            let new_gcost = g_cost + edge.length;
+            search.reach(edge.to, current, edge.name, new_gcost, new_gcost + h_cost(edge.to));
+        }
+    }
+    None
+}
+
+// * Bidirectional A*: one search forward from the start and one backward from the end, taking
+// * turns by whichever has the smaller key.  Both steer by the same potential, half of how much
+// * nearer the end a node is less half of how much further from the start, so no segment looks
+// * cheaper from one side than from the other and a node's first settling is still final.  Keys
+// * are g plus the potential going forward, g minus it going backward.  The best meeting seen so
+// * far is the shortest route once the two smallest keys add up to at least its length.
+fn bidirectional_a_star(
+    graph: &RouteGraph,
+    start: NodeId,
+    end: NodeId,
+) -> Option<(Vec<StreetSegment>, f64)> {
+    let (start_coord, end_coord) = (graph.coord(start), graph.coord(end));
+    let potential = |node: NodeId| {
+        let gc = graph.coord(node);
+        (provided::distance_earth_miles(gc, end_coord) - provided::distance_earth_miles(start_coord, gc))
+            / 2.
+    };
+    let mut forward = Frontier::new(graph.node_count(), start, potential(start));
+    let mut backward = Frontier::new(graph.node_count(), end, -potential(end));
+    let mut best = f64::INFINITY;
+    let mut meeting: Option<NodeId> = None;
+    loop {
+        let (forward_key, backward_key) = (forward.min_key(), backward.min_key());
+        if forward_key + backward_key >= best {
+            break;
+        }
+        let forwards = forward_key <= backward_key;
+        let (search, other) = if forwards {
+            (&mut forward, &backward)
+        } else {
+            (&mut backward, &forward)
+        };
+        let (current, g_cost) = search.settle()?;
+        let (edges, sign): (Vec<Edge>, f64) = if forwards {
+            (graph.leaving(current).collect(), 1.)
+        } else {
+            (graph.arriving(current).collect(), -1.)
+        };
+        for edge in edges {
+            let new_gcost = g_cost + edge.length;
+            let key = new_gcost + sign * potential(edge.to);
+            search.reach(edge.to, current, edge.name, new_gcost, key);
+            let rest = other.g_costs[edge.to as usize];
+            if new_gcost + rest < best {
+                best = new_gcost + rest;
+                meeting = Some(edge.to);
+            }
+        }
+    }
+    let meeting = meeting?;
+    let mut route = forward.route_back(graph, start, meeting);
+    route.extend(backward.route_on(graph, meeting, end));
+    Some((route, best))
+}
+
+// One direction's search: its best known g-costs, and the queue of nodes still to settle.
+struct Frontier {
+    // Infinity for nodes not reached yet.
+    g_costs: Vec<f64>,
+    // * The node each node was reached from and the street between them: the previous node
+    // * going forwards, the next one going backwards.
+    via: Vec<Option<(NodeId, NameId)>>,
+    closed: Vec<bool>,
+    // Nodes are used solely to compare keys in the priority queue.
+    nodes: BinaryHeap<Node>,
+}
+
+impl Frontier {
+    fn new(node_count: usize, origin: NodeId, key: f64) -> Frontier {
+        let mut frontier = Frontier {
+            g_costs: vec![f64::INFINITY; node_count],
+            via: vec![None; node_count],
+            closed: vec![false; node_count],
+            nodes: BinaryHeap::new(),
+        };
+        frontier.g_costs[origin as usize] = 0.;
+        frontier.nodes.push(Node::from(origin, key));
+        frontier
+    }
+    // The smallest key of any node left to settle.  Stale entries for settled nodes are dropped.
+    fn min_key(&mut self) -> f64 {
+        while let Some(node) = self.nodes.peek() {
+            if !self.closed[node.id as usize] {
+                return node.cost.into_inner();
+            }
+            self.nodes.pop();
+        }
+        f64::INFINITY
+    }
+    // Closes the unsettled node with the smallest key, and hands it back with its g-cost.
+    fn settle(&mut self) -> Option<(NodeId, f64)> {
+        while let Some(node) = self.nodes.pop() {
+            let id = node.id as usize;
+            if !self.closed[id] {
+                self.closed[id] = true;
+                return Some((node.id, self.g_costs[id]));
+            }
+        }
+        None
+    }
+    fn reach(&mut self, node: NodeId, from: NodeId, name: NameId, g_cost: f64, key: f64) {
+        let id = node as usize;
+        if self.closed[id] || self.g_costs[id] <= g_cost {
+            return;
+        }
+        self.g_costs[id] = g_cost;
+        self.via[id] = Some((from, name));
+        self.nodes.push(Node::from(node, key));
+    }
+    // The segments from start to node, for a forward search from start.
+    fn route_back(&self, graph: &RouteGraph, start: NodeId, node: NodeId) -> Vec<StreetSegment> {
+        let mut route = Vec::new();
+        let mut current = node;
+        while current != start {
+            let (previous, name) = self.via[current as usize].unwrap();
+            route.push(graph.segment(previous, current, name));
+            current = previous;
+        }
+        route.reverse();
+        route
+    }
+    // The segments from node to end, for a backward search from end.
+    fn route_on(&self, graph: &RouteGraph, node: NodeId, end: NodeId) -> Vec<StreetSegment> {
+        let mut route = Vec::new();
+        let mut current = node;
+        while current != end {
+            let (next, name) = self.via[current as usize].unwrap();
+            route.push(graph.segment(current, next, name));
+            current = next;
+        }
+        route
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLES: usize = 8;
+
+    fn load_map() -> StreetMap {
+        StreetMap::load_from(concat!(env!("CARGO_MANIFEST_DIR"), "/mapdata.txt")).unwrap()
+    }
+
+    // Intersections spread across the map, the same ones every run.
+    fn sample_coords(sm: &StreetMap) -> Vec<GeoCoord> {
+        let mut coords: Vec<GeoCoord> = sm.coords().cloned().collect();
+        coords.sort();
+        let step = coords.len() / SAMPLES;
+        coords.into_iter().step_by(step).take(SAMPLES).collect()
+    }
+
+    // Mid-block points halfway along some of the map's segments.
+    fn sample_snaps(sm: &StreetMap) -> Vec<Snap> {
+        sample_coords(sm)
+            .iter()
+            .filter_map(|coord| {
+                let seg = sm.get_segments_from(coord)?.next()?;
+                let halfway = GeoCoord::from_degrees(
+                    (seg.start.latitude() + seg.end.latitude()) / 2.,
+                    (seg.start.longitude() + seg.end.longitude()) / 2.,
+                );
+                sm.snap(&halfway)
+            })
+            .collect()
+    }
+
+    // The route has to join up from start to end, and its distance has to be its segments' lengths.
+    fn check_route(route: &[StreetSegment], distance: f64, start: &GeoCoord, end: &GeoCoord) {
+        let total: f64 = route.iter().map(|seg| seg.length()).sum();
+        assert!((distance - total).abs() < 1e-9, "{} != {}", distance, total);
+        if start == end {
+            assert!(route.is_empty());
+            return;
+        }
+        assert_eq!(&route.first().unwrap().start, start);
+        assert_eq!(&route.last().unwrap().end, end);
+        for pair in route.windows(2) {
+            assert_eq!(pair[0].end, pair[1].start);
+        }
+    }
+
+    fn check_all_pairs(search: RouteSearch) {
+        let sm = load_map();
+        let router = PointToPointRouter::from(&sm).with_search(search);
+        let coords = sample_coords(&sm);
+        let mut routed = 0;
+        for start in &coords {
+            for end in &coords {
+                if let Ok((route, distance)) = router.generate_route(start, end) {
+                    check_route(&route, distance, start, end);
+                    routed += 1;
+                }
+            }
+        }
+        assert!(routed > coords.len());
+    }
+
+    #[test]
+    fn a_star_distance_is_sum_of_segments() {
+        check_all_pairs(RouteSearch::AStar);
+    }
+
+    #[test]
+    fn bidirectional_distance_is_sum_of_segments() {
+        check_all_pairs(RouteSearch::Bidirectional);
+    }
+
+    #[test]
+    fn searches_agree_with_dijkstra() {
+        let sm = load_map();
+        let a_star = PointToPointRouter::from(&sm);
+        let bidirectional = PointToPointRouter::from(&sm).with_search(RouteSearch::Bidirectional);
+        let snaps = sample_snaps(&sm);
+        for from in &snaps {
+            let shortest = a_star.distances_from(from, &snaps, &Speeds::default());
+            for (to, &(expected, _)) in snaps.iter().zip(&shortest) {
+                for router in [&a_star, &bidirectional] {
+                    match router.route_between(from, to) {
+                        Ok((route, distance)) => {
+                            check_route(&route, distance, &from.location, &to.location);
+                            assert!((distance - expected).abs() < 1e-9, "{} != {}", distance, expected);
+                        }
+                        Err(_) => assert!(expected.is_infinite()),
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn hierarchy_agrees_with_a_star_after_save_and_load() {
+        let sm = load_map();
+        let built = ContractionHierarchy::build(&sm);
+        let path = std::env::temp_dir().join(format!("goober_eats_test_{}.ch", std::process::id()));
+        let path = path.to_str().unwrap();
+        built.save(path).unwrap();
+        let loaded = ContractionHierarchy::load(path, &sm);
+        std::fs::remove_file(path).unwrap();
+        let loaded = loaded.unwrap();
+
+        let a_star = PointToPointRouter::from(&sm);
+        let snaps = sample_snaps(&sm);
+        for ch in [&built, &loaded] {
+            let router = PointToPointRouter::from(&sm).with_hierarchy(ch);
+            for from in &snaps {
+                let shortest = a_star.distances_from(from, &snaps, &Speeds::default());
+                for (to, &(expected, _)) in snaps.iter().zip(&shortest) {
+                    match router.route_between(from, to) {
+                        Ok((route, distance)) => {
+                            check_route(&route, distance, &from.location, &to.location);
+                            assert!((distance - expected).abs() < 1e-9, "{} != {}", distance, expected);
+                        }
+                        Err(_) => assert!(expected.is_infinite()),
                     }
                 }
-            } else {
-                return Err(RouteError::new(DeliveryFailure::BadCoord));
             }
         }
-        return Err(RouteError::new(DeliveryFailure::NoRoute));
+    }
+
+    // * A one-way loop: Main Street runs A to B, Side Street B to C to D, and Back Street only
+    // * D to A.  Driving from B back to A has to go all the way round.
+    const ONE_WAY_LOOP: &str = "Main Street
+1
+34.0600 -118.4400 34.0600 -118.4390 >
+Side Street
+2 oneway
+34.0600 -118.4390 34.0610 -118.4390
+34.0610 -118.4390 34.0610 -118.4400
+Back Street
+1
+34.0600 -118.4400 34.0610 -118.4400 <
+";
+
+    #[test]
+    fn one_way_streets_force_a_detour() {
+        let sm = StreetMap::read_text(ONE_WAY_LOOP.as_bytes());
+        let a = GeoCoord::from("34.0600", "-118.4400");
+        let b = GeoCoord::from("34.0600", "-118.4390");
+        // Halfway along Main Street, the way back to A still starts by heading on to B.
+        let middle = sm.snap(&GeoCoord::from("34.0600", "-118.4395")).unwrap();
+        assert!(middle.segment.is_some());
+        let names = |route: &[StreetSegment]| -> Vec<String> {
+            route.iter().map(|seg| seg.name.clone()).collect()
+        };
+
+        for search in [RouteSearch::AStar, RouteSearch::Bidirectional] {
+            let router = PointToPointRouter::from(&sm).with_search(search);
+            let (route, distance) = router.generate_route(&a, &b).unwrap();
+            check_route(&route, distance, &a, &b);
+            assert_eq!(names(&route), ["Main Street"]);
+            let (route, distance) = router.generate_route(&b, &a).unwrap();
+            check_route(&route, distance, &b, &a);
+            assert_eq!(names(&route), ["Side Street", "Side Street", "Back Street"]);
+
+            let (route, distance) = router.route_between(&middle, &Snap::at(&a)).unwrap();
+            check_route(&route, distance, &middle.location, &a);
+            assert_eq!(route[0].end, b);
+            assert_eq!(route.len(), 4);
+        }
     }
 }
@@ -1,4 +1,5 @@
 #![allow(dead_code)]
+use crate::schedule::format_clock;
 use std::cmp::Ordering;
 use std::hash::{Hash, Hasher};
 
@@ -43,6 +44,28 @@ impl GeoCoord {
             longitude: lon.trim().parse::<f64>().unwrap(),
         }
     }
+    // * Builds a coordinate that doesn't come from map text, e.g. a projected point.
+    // * The text uses the same seven decimal places as mapdata.txt.
+    pub fn from_degrees(lat: f64, lon: f64) -> GeoCoord {
+        GeoCoord {
+            lat_text: format!("{:.7}", lat),
+            lon_text: format!("{:.7}", lon),
+            latitude: lat,
+            longitude: lon,
+        }
+    }
+    pub fn latitude(&self) -> f64 {
+        self.latitude
+    }
+    pub fn longitude(&self) -> f64 {
+        self.longitude
+    }
+    pub fn lat_text(&self) -> &str {
+        &self.lat_text
+    }
+    pub fn lon_text(&self) -> &str {
+        &self.lon_text
+    }
 }
 
 impl PartialEq for GeoCoord {
@@ -110,6 +133,23 @@ impl Eq for StreetSegment {}
 pub struct DeliveryRequest {
     pub item: String,
     pub location: GeoCoord,
+    // How much of a vehicle's capacity the item takes up, e.g. bags or pounds.
+    pub demand: u32,
+    // The promised window, in minutes after midnight.
+    pub earliest: Option<f64>,
+    pub latest: Option<f64>,
+    // Where the item has to be collected first, e.g. a restaurant.  Without one it comes from the depot.
+    pub pickup: Option<GeoCoord>,
+    pub kind: StopKind,
+    // How far location had to move to reach a street, once the planner has moved it there.
+    pub snap_distance: f64,
+}
+
+// What a vehicle does at a stop.  A request with a pickup becomes two stops in a tour.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StopKind {
+    Dropoff,
+    Pickup,
 }
 
 impl DeliveryRequest {
@@ -117,8 +157,25 @@ impl DeliveryRequest {
         DeliveryRequest {
             item: it.to_string(),
             location: loc.clone(),
+            demand: 1,
+            earliest: None,
+            latest: None,
+            pickup: None,
+            kind: StopKind::Dropoff,
+            snap_distance: 0.,
         }
     }
+    // * The stop where a paired request's item is collected.  Windows only apply to the drop-off.
+    pub fn pickup_stop(&self) -> Option<DeliveryRequest> {
+        let pickup = self.pickup.as_ref()?;
+        Some(DeliveryRequest {
+            location: pickup.clone(),
+            earliest: None,
+            latest: None,
+            kind: StopKind::Pickup,
+            ..self.clone()
+        })
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -127,6 +184,8 @@ enum CommandType {
     Proceed,
     Turn,
     Deliver,
+    Pickup,
+    Reload,
 }
 
 #[derive(Clone, Debug)]
@@ -136,6 +195,8 @@ pub struct DeliveryCommand {
     street_name: String,
     item: String,
     distance: f64,
+    // When a delivery is expected to happen, in minutes after midnight.
+    eta: Option<f64>,
 }
 
 impl DeliveryCommand {
@@ -146,6 +207,7 @@ impl DeliveryCommand {
             street_name,
             item: String::default(),
             distance,
+            eta: None,
         }
     }
     pub fn init_proceed(&mut self, direction: String, street_name: String, distance: f64) {
@@ -161,6 +223,7 @@ impl DeliveryCommand {
             street_name,
             item: String::default(),
             distance: f64::default(),
+            eta: None,
         }
     }
     pub fn init_turn(&mut self, direction: String, street_name: String) {
@@ -175,18 +238,58 @@ impl DeliveryCommand {
             street_name: String::default(),
             item,
             distance: f64::default(),
+            eta: None,
+        }
+    }
+    pub fn new_pickup(item: String) -> Self {
+        DeliveryCommand {
+            command: CommandType::Pickup,
+            item,
+            ..DeliveryCommand::default()
+        }
+    }
+    pub fn new_reload() -> Self {
+        DeliveryCommand {
+            command: CommandType::Reload,
+            ..DeliveryCommand::default()
         }
     }
     pub fn init_deliver(&mut self, item: String) {
         self.command = CommandType::Deliver;
         self.item = item;
     }
+    pub fn set_eta(&mut self, eta: f64) {
+        self.eta = Some(eta);
+    }
     pub fn increase_distance(&mut self, more_distance: f64) {
         self.distance += more_distance;
     }
     pub fn street_name(&self) -> &str {
         &self.street_name
     }
+    // What kind of command this is, as one lowercase word.
+    pub fn kind(&self) -> &'static str {
+        match self.command {
+            CommandType::Invalid => "invalid",
+            CommandType::Proceed => "proceed",
+            CommandType::Turn => "turn",
+            CommandType::Deliver => "deliver",
+            CommandType::Pickup => "pickup",
+            CommandType::Reload => "reload",
+        }
+    }
+    pub fn direction(&self) -> &str {
+        &self.direction
+    }
+    pub fn item(&self) -> &str {
+        &self.item
+    }
+    pub fn distance(&self) -> f64 {
+        self.distance
+    }
+    pub fn eta(&self) -> Option<f64> {
+        self.eta
+    }
 }
 
 impl std::fmt::Display for DeliveryCommand {
@@ -199,7 +302,15 @@ impl std::fmt::Display for DeliveryCommand {
                 self.direction, self.street_name, self.distance
             ),
             CommandType::Turn => write!(f, "Turn {} on {}", self.direction, self.street_name),
-            CommandType::Deliver => write!(f, "Deliver {}", self.item),
+            CommandType::Deliver => match self.eta {
+                Some(eta) => write!(f, "Deliver {} (ETA {})", self.item, format_clock(eta)),
+                None => write!(f, "Deliver {}", self.item),
+            },
+            CommandType::Pickup => match self.eta {
+                Some(eta) => write!(f, "Pick up {} (ETA {})", self.item, format_clock(eta)),
+                None => write!(f, "Pick up {}", self.item),
+            },
+            CommandType::Reload => write!(f, "Reload at the depot"),
         }
     }
 }
@@ -212,6 +323,7 @@ impl Default for DeliveryCommand {
             street_name: String::default(),
             item: String::default(),
             distance: f64::default(),
+            eta: None,
         }
     }
 }
@@ -228,7 +340,6 @@ impl Default for DeliveryCommand {
 * @param lon2d Longitude of the second point in degrees
 * @return The distance between the two points in kilometers
 */
-
 pub fn distance_earth_km(g1: &GeoCoord, g2: &GeoCoord) -> f64 {
     let lat1r = g1.latitude.to_radians();
     let lon1r = g1.longitude.to_radians();
@@ -236,9 +347,7 @@ pub fn distance_earth_km(g1: &GeoCoord, g2: &GeoCoord) -> f64 {
     let lon2r = g2.longitude.to_radians();
     let u = f64::sin((lat2r - lat1r) / 2.0);
     let v = f64::sin((lon2r - lon1r) / 2.0);
-    return 2.0
-        * EARTH_RADIUS_KM
-        * f64::asin((u * u + f64::cos(lat1r) * f64::cos(lat2r) * v * v).sqrt());
+    2.0 * EARTH_RADIUS_KM * f64::asin((u * u + f64::cos(lat1r) * f64::cos(lat2r) * v * v).sqrt())
 }
 
 pub fn distance_earth_miles(g1: &GeoCoord, g2: &GeoCoord) -> f64 {
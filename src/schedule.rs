@@ -0,0 +1,95 @@
+use crate::provided::StreetSegment;
+use std::collections::HashMap;
+
+// Used for every street without a speed of its own.
+const DEFAULT_SPEED_MPH: f64 = 25.;
+
+// * How fast vehicles drive, overall and on particular streets.
+#[derive(Clone, Debug)]
+pub struct Speeds {
+    pub default_mph: f64,
+    pub streets: HashMap<String, f64>,
+}
+
+impl Speeds {
+    pub fn new(default_mph: f64) -> Speeds {
+        Speeds {
+            default_mph,
+            streets: HashMap::new(),
+        }
+    }
+    pub fn mph(&self, street: &str) -> f64 {
+        self.streets.get(street).copied().unwrap_or(self.default_mph)
+    }
+    // Minutes to drive the whole segment.
+    pub fn minutes(&self, seg: &StreetSegment) -> f64 {
+        seg.length() / self.mph(&seg.name) * 60.
+    }
+}
+
+impl Default for Speeds {
+    fn default() -> Self {
+        Speeds::new(DEFAULT_SPEED_MPH)
+    }
+}
+
+// What to do with deliveries that can't make their window.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LatePolicy {
+    // Deliver everything, keeping total lateness as small as possible.
+    MinimizeLateness,
+    // Leave out deliveries that would be late even driving straight there from the depot.
+    RejectInfeasible,
+}
+
+// * When the vehicles leave the depot and how fast they go.  Times are minutes after midnight.
+#[derive(Clone, Debug)]
+pub struct Schedule {
+    pub departure: f64,
+    pub speeds: Speeds,
+    pub late_policy: LatePolicy,
+}
+
+// Reads a 24-hour clock time such as "1130" or "930" into minutes after midnight.
+pub fn parse_clock(text: &str) -> Option<f64> {
+    let text = text.trim();
+    if text.len() < 3 || text.len() > 4 || !text.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let (hours, minutes) = text.split_at(text.len() - 2);
+    let (hours, minutes): (u32, u32) = (hours.parse().ok()?, minutes.parse().ok()?);
+    if hours > 23 || minutes > 59 {
+        return None;
+    }
+    Some((hours * 60 + minutes) as f64)
+}
+
+pub fn format_clock(minutes: f64) -> String {
+    let minutes = minutes.round() as i64;
+    format!("{:02}:{:02}", (minutes / 60) % 24, minutes % 60)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clocks_read_as_minutes_after_midnight() {
+        assert_eq!(parse_clock("0000"), Some(0.));
+        assert_eq!(parse_clock("930"), Some(570.));
+        assert_eq!(parse_clock(" 1130 "), Some(690.));
+        assert_eq!(parse_clock("2359"), Some(1439.));
+        for bad in ["2400", "0960", "93", "", "12345", "9:30", "-930"] {
+            assert_eq!(parse_clock(bad), None, "{:?}", bad);
+        }
+    }
+
+    #[test]
+    fn clocks_print_on_a_24_hour_dial() {
+        assert_eq!(format_clock(0.), "00:00");
+        assert_eq!(format_clock(570.), "09:30");
+        assert_eq!(format_clock(1439.4), "23:59");
+        // * Routes that run past midnight wrap around.
+        assert_eq!(format_clock(1500.), "01:00");
+    }
+}
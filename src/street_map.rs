@@ -1,40 +1,180 @@
-use crate::provided::{GeoCoord, StreetSegment};
+use crate::binary::{check_fits, invalid, read_coord, read_count, read_f64, read_str, read_u32};
+use crate::binary::{read_u64, write_f64, write_str};
+use crate::binary::{write_u32, write_u64};
+use crate::osm;
+use crate::provided::{distance_earth_miles, GeoCoord, StreetSegment};
+use memmap2::Mmap;
 use std::collections::HashMap;
 use std::fs::File;
-use std::io::{BufRead, BufReader};
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
 use std::path::Path;
+use std::time::UNIX_EPOCH;
+
+const MAGIC: &[u8; 4] = b"GEMP";
+const FORMAT_VERSION: u32 = 1;
+
+// Side length of a spatial index cell, in degrees.  Roughly 0.14 miles of latitude.
+const GRID_CELL_DEGREES: f64 = 0.002;
+// Stops further than this from every street are taken to be mistyped.
+const MAX_SNAP_MILES: f64 = 1.;
+
+type Cell = (i32, i32);
+
+// Which way traffic may travel along a segment, relative to how it is written in the map file.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Direction {
+    Both,
+    Forward,
+    Backward,
+}
+
+// The result of snapping an arbitrary coordinate onto the street network.
+#[derive(Clone, Debug)]
+pub struct Snap {
+    pub location: GeoCoord,
+    // The segment location lies partway along, or None if location is an intersection.
+    pub segment: Option<StreetSegment>,
+    pub distance: f64,
+}
+
+impl Snap {
+    // A point that is already known to be an intersection.
+    pub fn at(gc: &GeoCoord) -> Snap {
+        Snap {
+            location: gc.clone(),
+            segment: None,
+            distance: 0.,
+        }
+    }
+}
+
+// Nodes are numbered from 0, in the order the map file first mentions them.
+pub type NodeId = u32;
+// Street names are stored once each, and referred to by number.
+pub type NameId = u32;
+
+// One way of driving along a segment.  Among the edges into a node, `to` is where they come from.
+#[derive(Clone, Copy, Debug)]
+pub struct Edge {
+    pub to: NodeId,
+    pub name: NameId,
+    pub length: f64,
+}
+
+// * Compressed sparse row adjacency: node v's edges are edges[offsets[v]..offsets[v + 1]].
+struct Adjacency {
+    offsets: Vec<u32>,
+    edges: Vec<Edge>,
+}
+
+impl Adjacency {
+    // Each node's edges keep the order they're given in.
+    fn build(node_count: usize, arcs: &[(NodeId, Edge)]) -> Adjacency {
+        let mut offsets = vec![0u32; node_count + 1];
+        for &(from, _) in arcs {
+            offsets[from as usize + 1] += 1;
+        }
+        for v in 0..node_count {
+            offsets[v + 1] += offsets[v];
+        }
+        let mut next = offsets.clone();
+        let mut edges = vec![
+            Edge {
+                to: 0,
+                name: 0,
+                length: 0.,
+            };
+            arcs.len()
+        ];
+        for &(from, edge) in arcs {
+            edges[next[from as usize] as usize] = edge;
+            next[from as usize] += 1;
+        }
+        Adjacency { offsets, edges }
+    }
+    fn of(&self, node: NodeId) -> &[Edge] {
+        let node = node as usize;
+        &self.edges[self.offsets[node] as usize..self.offsets[node + 1] as usize]
+    }
+}
 
 pub struct StreetMap {
-    streets: HashMap<GeoCoord, Vec<StreetSegment>>,
+    // By node.
+    coords: Vec<GeoCoord>,
+    ids: HashMap<GeoCoord, NodeId>,
+    // By name.
+    names: Vec<String>,
+    name_ids: HashMap<String, NameId>,
+    leaving: Adjacency,
+    // The same edges, filed under the node they arrive at, for searching backwards.
+    arriving: Adjacency,
+    // Every segment as the map file gives it: start, end and name.
+    segments: Vec<(NodeId, NodeId, NameId)>,
+    // * Spatial index: every segment is filed under each grid cell its bounding box touches.
+    grid: HashMap<Cell, Vec<u32>>,
+    // Inclusive (min, max) cell indices, so nearest-segment searches know when to give up.
+    grid_bounds: Option<(Cell, Cell)>,
+    // * By node: whether it's in the map's largest strongly connected part.  Stops only snap
+    // * there, so that every stop can reach every other one.
+    connected: Vec<bool>,
 }
 
 impl StreetMap {
     pub fn new() -> StreetMap {
-        StreetMap {
-            streets: HashMap::new(),
-        }
+        MapBuilder::default().finish()
     }
+    // * map_file may be a text map or one written by save_compiled.  A text map loads from its
+    // * compiled copy (see compiled_path) instead when there is one made from this very file.
     pub fn load_from(map_file: &str) -> Result<StreetMap, std::io::Error> {
+        let mut magic = [0u8; 4];
+        let mut file = File::open(map_file)?;
+        if file.read_exact(&mut magic).is_ok() && &magic == MAGIC {
+            return StreetMap::load_compiled(map_file, None);
+        }
+        let stamp = source_stamp(map_file)?;
+        if let Ok(sm) = StreetMap::load_compiled(&StreetMap::compiled_path(map_file), Some(stamp)) {
+            return Ok(sm);
+        }
         let mut sm = StreetMap::new();
         sm.load(map_file)?;
         Ok(sm)
     }
+    // Where load_from looks for a text map's compiled copy.
+    pub fn compiled_path(map_file: &str) -> String {
+        format!("{}.bin", map_file)
+    }
+    // * Replaces whatever the map held with the streets in map_file: an OpenStreetMap extract if
+    // * its name ends in .osm, otherwise the course's text format.
     pub fn load(&mut self, map_file: &str) -> Result<(), std::io::Error> {
+        if map_file.ends_with(".osm") {
+            *self = osm::load_osm(map_file)?;
+            return Ok(());
+        }
+        // * Reading PBF needs the osmpbf crate, which isn't among this build's dependencies.
+        if map_file.ends_with(".pbf") {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "PBF extracts aren't supported; convert to .osm first, e.g. with osmium cat",
+            ));
+        }
         let file_path = Path::new(map_file);
         let file_handle = File::open(file_path)?;
-        let file_read = BufReader::new(file_handle);
-
+        *self = StreetMap::read_text(BufReader::new(file_handle));
+        Ok(())
+    }
+    // Parses the course's text format: each street's name, its segment count, then its segments.
+    pub fn read_text(file_read: impl BufRead) -> StreetMap {
         let mut street_count: i32;
         let mut coords: String;
+        let mut builder = MapBuilder::default();
 
-        let liter: Vec<String> = file_read.lines().filter_map(|line| line.ok()).collect();
-        let mut liter = liter.into_iter();
+        let mut liter = file_read.lines().map_while(Result::ok);
         while let Some(name) = liter.next() {
-            street_count = liter
-                .next()
-                .unwrap()
-                .parse()
-                .unwrap_or(0);
+            // * The count line may be followed by "oneway": every segment then only runs start to end.
+            let count_line = liter.next().unwrap();
+            let mut count_words = count_line.split_ascii_whitespace();
+            street_count = count_words.next().unwrap_or("0").parse().unwrap_or(0);
+            let street_oneway = count_words.any(|word| word == "oneway");
 
             for _i in 0..street_count {
                 coords = liter.next().expect("Expected coords.");
@@ -49,35 +189,638 @@ impl StreetMap {
                     &each_coord[2],
                     &each_coord[3],
                 );
+                // * A trailing ">" means traffic only flows start to end, "<" only end to start.
+                let direction = match each_coord.get(4).map(|word| word.as_str()) {
+                    Some(">") => Direction::Forward,
+                    Some("<") => Direction::Backward,
+                    _ if street_oneway => Direction::Forward,
+                    _ => Direction::Both,
+                };
+
+                let start = GeoCoord::from(coord_lat1, coord_lon1);
+                let end = GeoCoord::from(coord_lat2, coord_lon2);
+                builder.add_segment(start, end, &name, direction);
+            }
+        }
+        builder.finish()
+    }
+    // * Writes the map as laid out in memory, ready to load without parsing or indexing again.
+    // * The header records source_file's size and modification time, so load_from can tell
+    // * whether the copy is still up to date.
+    pub fn save_compiled(&self, path: &str, source_file: &str) -> io::Result<()> {
+        let (size, modified) = source_stamp(source_file)?;
+        let mut w = BufWriter::new(File::create(path)?);
+        w.write_all(MAGIC)?;
+        write_u32(&mut w, FORMAT_VERSION)?;
+        write_u64(&mut w, size)?;
+        write_u64(&mut w, modified)?;
+        write_u32(&mut w, self.coords.len() as u32)?;
+        for coord in &self.coords {
+            write_str(&mut w, coord.lat_text())?;
+            write_str(&mut w, coord.lon_text())?;
+        }
+        write_u32(&mut w, self.names.len() as u32)?;
+        for name in &self.names {
+            write_str(&mut w, name)?;
+        }
+        for adjacency in &[&self.leaving, &self.arriving] {
+            for &offset in &adjacency.offsets {
+                write_u32(&mut w, offset)?;
+            }
+            for edge in &adjacency.edges {
+                write_u32(&mut w, edge.to)?;
+                write_u32(&mut w, edge.name)?;
+                write_f64(&mut w, edge.length)?;
+            }
+        }
+        write_u32(&mut w, self.segments.len() as u32)?;
+        for &(start, end, name) in &self.segments {
+            write_u32(&mut w, start)?;
+            write_u32(&mut w, end)?;
+            write_u32(&mut w, name)?;
+        }
+        // * Cells go in order, so the same map always compiles to the same bytes.
+        let mut cells: Vec<_> = self.grid.iter().collect();
+        cells.sort_unstable_by_key(|&(&cell, _)| cell);
+        write_u32(&mut w, cells.len() as u32)?;
+        for (&(lat, lon), indices) in cells {
+            write_u32(&mut w, lat as u32)?;
+            write_u32(&mut w, lon as u32)?;
+            write_u32(&mut w, indices.len() as u32)?;
+            for &index in indices {
+                write_u32(&mut w, index)?;
+            }
+        }
+        w.flush()
+    }
+    // * Reads a map written by save_compiled.  With a stamp, refuses copies of any other version
+    // * of the text map than the one the stamp describes.
+    fn load_compiled(path: &str, stamp: Option<(u64, u64)>) -> io::Result<StreetMap> {
+        let file = File::open(path)?;
+        // * Safety: the mapping is only read while decoding, and the program never writes to it.
+        // * Another process truncating the file meanwhile could still fault, as with any mmap.
+        let mapped = unsafe { Mmap::map(&file)? };
+        let mut r: &[u8] = &mapped;
+        let mut magic = [0u8; 4];
+        r.read_exact(&mut magic)?;
+        if &magic != MAGIC || read_u32(&mut r)? != FORMAT_VERSION {
+            return Err(invalid("not a compiled map of this version; run compile-map again"));
+        }
+        let stored = (read_u64(&mut r)?, read_u64(&mut r)?);
+        if stamp.is_some_and(|stamp| stamp != stored) {
+            return Err(invalid("compiled map is out of date"));
+        }
 
-                let start = GeoCoord::from(&coord_lat1, &coord_lon1);
-                let end = GeoCoord::from(&coord_lat2, &coord_lon2);
-                let segment = StreetSegment::from(&start, &end, name.as_str());
-                let rev_segment = segment.reverse_segment();
+        // Coordinates are two strings, each at least a length.
+        let node_count = read_count(&mut r, 8)?;
+        let mut coords = Vec::with_capacity(node_count);
+        let mut ids = HashMap::with_capacity(node_count);
+        for node in 0..node_count {
+            let gc = read_coord(&mut r)?;
+            ids.insert(gc.clone(), node as NodeId);
+            coords.push(gc);
+        }
+        let name_count = read_count(&mut r, 4)?;
+        let mut names = Vec::with_capacity(name_count);
+        let mut name_ids = HashMap::with_capacity(name_count);
+        for name in 0..name_count {
+            let text = read_str(&mut r)?;
+            name_ids.insert(text.clone(), name as NameId);
+            names.push(text);
+        }
+        let leaving = read_adjacency(&mut r, node_count, name_count)?;
+        let arriving = read_adjacency(&mut r, node_count, name_count)?;
 
-                if let Some(start_vec) = self.streets.get_mut(&start) {
-                    start_vec.push(segment);
-                } else {
-                    let segs = vec![segment];
-                    self.streets.insert(start, segs);
+        let segment_count = read_count(&mut r, 12)?;
+        let mut segments = Vec::with_capacity(segment_count);
+        for _ in 0..segment_count {
+            let segment = (read_u32(&mut r)?, read_u32(&mut r)?, read_u32(&mut r)?);
+            if segment.0 as usize >= node_count
+                || segment.1 as usize >= node_count
+                || segment.2 as usize >= name_count
+            {
+                return Err(invalid("segment refers to a missing node or name"));
+            }
+            segments.push(segment);
+        }
+        // Cells are two indices and a count.
+        let cell_count = read_count(&mut r, 12)?;
+        let mut grid = HashMap::with_capacity(cell_count);
+        let mut grid_bounds: Option<(Cell, Cell)> = None;
+        for _ in 0..cell_count {
+            let cell = (read_u32(&mut r)? as i32, read_u32(&mut r)? as i32);
+            let index_count = read_count(&mut r, 4)?;
+            let mut indices = Vec::with_capacity(index_count);
+            for _ in 0..index_count {
+                let index = read_u32(&mut r)?;
+                if index as usize >= segment_count {
+                    return Err(invalid("spatial index refers to a missing segment"));
                 }
-                if let Some(end_vec) = self.streets.get_mut(&end) {
-                    end_vec.push(rev_segment);
+                indices.push(index);
+            }
+            grid.insert(cell, indices);
+            grid_bounds = Some(match grid_bounds {
+                None => (cell, cell),
+                Some((lo, hi)) => (
+                    (lo.0.min(cell.0), lo.1.min(cell.1)),
+                    (hi.0.max(cell.0), hi.1.max(cell.1)),
+                ),
+            });
+        }
+        if !r.is_empty() {
+            return Err(invalid("compiled map has trailing data"));
+        }
+
+        Ok(StreetMap {
+            connected: largest_component(&leaving, &arriving),
+            coords,
+            ids,
+            names,
+            name_ids,
+            leaving,
+            arriving,
+            segments,
+            grid,
+            grid_bounds,
+        })
+    }
+    pub fn node_count(&self) -> usize {
+        self.coords.len()
+    }
+    pub fn node(&self, gc: &GeoCoord) -> Option<NodeId> {
+        self.ids.get(gc).copied()
+    }
+    pub fn coord(&self, node: NodeId) -> &GeoCoord {
+        &self.coords[node as usize]
+    }
+    pub fn name_count(&self) -> usize {
+        self.names.len()
+    }
+    pub fn name(&self, name: NameId) -> &str {
+        &self.names[name as usize]
+    }
+    pub fn name_id(&self, name: &str) -> Option<NameId> {
+        self.name_ids.get(name).copied()
+    }
+    // Edges traffic can drive along out of node.
+    pub fn edges_from(&self, node: NodeId) -> &[Edge] {
+        self.leaving.of(node)
+    }
+    // Edges traffic can drive along into node, each with `to` naming where it comes from.
+    pub fn edges_into(&self, node: NodeId) -> &[Edge] {
+        self.arriving.of(node)
+    }
+    pub fn segment(&self, from: NodeId, to: NodeId, name: NameId) -> StreetSegment {
+        StreetSegment::from(self.coord(from), self.coord(to), self.name(name))
+    }
+    // * Segments that traffic can drive along out of gc, built on demand from the graph.
+    // * None if gc isn't on the map; a dead end of a one-way street gives an empty iterator.
+    pub fn get_segments_from(
+        &self,
+        gc: &GeoCoord,
+    ) -> Option<impl Iterator<Item = StreetSegment> + '_> {
+        let node = self.node(gc)?;
+        Some(
+            self.edges_from(node)
+                .iter()
+                .map(move |edge| self.segment(node, edge.to, edge.name)),
+        )
+    }
+    // Every segment as the map file gives it: start, end and name.
+    pub fn segments(&self) -> impl Iterator<Item = (NodeId, NodeId, NameId)> + '_ {
+        self.segments.iter().copied()
+    }
+    // Every intersection and segment end in the map, in no particular order.
+    pub fn coords(&self) -> impl Iterator<Item = &GeoCoord> {
+        self.coords.iter()
+    }
+    // * Finds the closest point on any street to gc.  Routes start and end right there,
+    // * even partway down a block.  Intersections snap to themselves at zero distance.
+    // * None if there's no street within MAX_SNAP_MILES, or only streets cut off from the rest.
+    pub fn snap(&self, gc: &GeoCoord) -> Option<Snap> {
+        if self.node(gc).is_some_and(|node| self.connected[node as usize]) {
+            return Some(Snap::at(gc));
+        }
+        let (segment, t, distance) = self.nearest_segment(gc)?;
+        if distance > MAX_SNAP_MILES {
+            return None;
+        }
+        let location = point_along(&segment, t);
+        if t <= 0. || location == segment.start {
+            return Some(Snap {
+                location: segment.start,
+                segment: None,
+                distance,
+            });
+        } else if t >= 1. || location == segment.end {
+            return Some(Snap {
+                location: segment.end,
+                segment: None,
+                distance,
+            });
+        }
+        Some(Snap {
+            location,
+            segment: Some(segment),
+            distance,
+        })
+    }
+    // Returns the nearest segment whose ends are both in the connected part of the map, how far
+    // along it the projection of gc falls (0 to 1), and the distance in miles from gc to that
+    // projection.
+    pub fn nearest_segment(&self, gc: &GeoCoord) -> Option<(StreetSegment, f64, f64)> {
+        let ((min_lat, min_lon), (max_lat, max_lon)) = self.grid_bounds?;
+        let (lat, lon) = cell_of(gc);
+        let cell_miles = f64::min(
+            distance_earth_miles(
+                gc,
+                &GeoCoord::from_degrees(gc.latitude() + GRID_CELL_DEGREES, gc.longitude()),
+            ),
+            distance_earth_miles(
+                gc,
+                &GeoCoord::from_degrees(gc.latitude(), gc.longitude() + GRID_CELL_DEGREES),
+            ),
+        );
+        let max_ring = [lat - min_lat, max_lat - lat, lon - min_lon, max_lon - lon]
+            .iter()
+            .map(|d| d.abs())
+            .max()
+            .unwrap();
+        // * Rings that don't reach the indexed area are empty, so a point far outside the map
+        // * starts at the first one that does.
+        let first_ring = *[min_lat - lat, lat - max_lat, min_lon - lon, lon - max_lon, 0]
+            .iter()
+            .max()
+            .unwrap();
+
+        let mut best: Option<(StreetSegment, f64, f64)> = None;
+        for ring in first_ring..=max_ring {
+            // * Only the border of the ring, and only the cells within the indexed area.
+            let (low_j, high_j) = ((lon - ring).max(min_lon), (lon + ring).min(max_lon));
+            for i in (lat - ring).max(min_lat)..=(lat + ring).min(max_lat) {
+                if (i - lat).abs() == ring {
+                    for j in low_j..=high_j {
+                        self.search_cell(gc, (i, j), &mut best);
+                    }
                 } else {
-                    let segs = vec![rev_segment];
-                    self.streets.insert(end, segs);
+                    for j in [lon - ring, lon + ring] {
+                        if (low_j..=high_j).contains(&j) {
+                            self.search_cell(gc, (i, j), &mut best);
+                        }
+                    }
+                }
+            }
+            // * Anything in an outer ring is at least this far away.
+            if let Some((_, _, d)) = best {
+                if d <= ring as f64 * cell_miles {
+                    break;
                 }
             }
         }
+        best
+    }
+    fn search_cell(&self, gc: &GeoCoord, cell: Cell, best: &mut Option<(StreetSegment, f64, f64)>) {
+        for &index in self.grid.get(&cell).into_iter().flatten() {
+            let (start, end, name) = self.segments[index as usize];
+            if !self.connected[start as usize] || !self.connected[end as usize] {
+                continue;
+            }
+            let seg = self.segment(start, end, name);
+            let (t, distance) = project_onto(gc, &seg);
+            if best.as_ref().is_none_or(|(_, _, d)| distance < *d) {
+                *best = Some((seg, t, distance));
+            }
+        }
+    }
+}
 
-        Ok(())
+// Collects a map's nodes, names and segments, then lays them out as a StreetMap.
+#[derive(Default)]
+pub struct MapBuilder {
+    coords: Vec<GeoCoord>,
+    ids: HashMap<GeoCoord, NodeId>,
+    names: Vec<String>,
+    name_ids: HashMap<String, NameId>,
+    // Edges by the node they leave, in the order the map file gives them.
+    arcs: Vec<(NodeId, Edge)>,
+    segments: Vec<(NodeId, NodeId, NameId)>,
+}
+
+impl MapBuilder {
+    fn node(&mut self, gc: GeoCoord) -> NodeId {
+        if let Some(&node) = self.ids.get(&gc) {
+            return node;
+        }
+        let node = self.coords.len() as NodeId;
+        self.coords.push(gc.clone());
+        self.ids.insert(gc, node);
+        node
+    }
+    fn name(&mut self, name: &str) -> NameId {
+        if let Some(&id) = self.name_ids.get(name) {
+            return id;
+        }
+        let id = self.names.len() as NameId;
+        self.names.push(name.to_string());
+        self.name_ids.insert(name.to_string(), id);
+        id
+    }
+    // Both ends always become nodes, so a dead end of a one-way street is still routable.
+    pub fn add_segment(&mut self, start: GeoCoord, end: GeoCoord, name: &str, direction: Direction) {
+        let length = distance_earth_miles(&start, &end);
+        let (from, to, name) = (self.node(start), self.node(end), self.name(name));
+        self.segments.push((from, to, name));
+        if direction != Direction::Backward {
+            self.arcs.push((from, Edge { to, name, length }));
+        }
+        if direction != Direction::Forward {
+            self.arcs.push((to, Edge { to: from, name, length }));
+        }
+    }
+    pub fn finish(self) -> StreetMap {
+        let node_count = self.coords.len();
+        let incoming: Vec<(NodeId, Edge)> = self
+            .arcs
+            .iter()
+            .map(|&(from, edge)| (edge.to, Edge { to: from, ..edge }))
+            .collect();
+        let mut grid: HashMap<Cell, Vec<u32>> = HashMap::new();
+        let mut grid_bounds: Option<(Cell, Cell)> = None;
+        for (index, &(start, end, _)) in self.segments.iter().enumerate() {
+            let (lat1, lon1) = cell_of(&self.coords[start as usize]);
+            let (lat2, lon2) = cell_of(&self.coords[end as usize]);
+            let low = (lat1.min(lat2), lon1.min(lon2));
+            let high = (lat1.max(lat2), lon1.max(lon2));
+            for i in low.0..=high.0 {
+                for j in low.1..=high.1 {
+                    grid.entry((i, j)).or_default().push(index as u32);
+                }
+            }
+            grid_bounds = Some(match grid_bounds {
+                None => (low, high),
+                Some((lo, hi)) => (
+                    (lo.0.min(low.0), lo.1.min(low.1)),
+                    (hi.0.max(high.0), hi.1.max(high.1)),
+                ),
+            });
+        }
+        let leaving = Adjacency::build(node_count, &self.arcs);
+        let arriving = Adjacency::build(node_count, &incoming);
+        StreetMap {
+            connected: largest_component(&leaving, &arriving),
+            leaving,
+            arriving,
+            coords: self.coords,
+            ids: self.ids,
+            names: self.names,
+            name_ids: self.name_ids,
+            segments: self.segments,
+            grid,
+            grid_bounds,
+        }
+    }
+}
+
+// * Kosaraju's algorithm: nodes in the largest strongly connected component, i.e. the biggest
+// * set of nodes that can all drive to one another.
+fn largest_component(leaving: &Adjacency, arriving: &Adjacency) -> Vec<bool> {
+    let node_count = leaving.offsets.len() - 1;
+    // Depth-first searches along the edges, noting nodes in the order they're finished with.
+    let mut visited = vec![false; node_count];
+    let mut finished: Vec<NodeId> = Vec::with_capacity(node_count);
+    let mut stack: Vec<(NodeId, usize)> = Vec::new();
+    for root in 0..node_count {
+        if visited[root] {
+            continue;
+        }
+        visited[root] = true;
+        stack.push((root as NodeId, 0));
+        while let Some(&(node, next)) = stack.last() {
+            match leaving.of(node).get(next) {
+                Some(edge) => {
+                    stack.last_mut().unwrap().1 += 1;
+                    if !visited[edge.to as usize] {
+                        visited[edge.to as usize] = true;
+                        stack.push((edge.to, 0));
+                    }
+                }
+                None => {
+                    finished.push(node);
+                    stack.pop();
+                }
+            }
+        }
+    }
+    // * Then against the edges, last finished first: each search gathers one component.
+    let mut component = vec![usize::MAX; node_count];
+    let mut sizes: Vec<usize> = Vec::new();
+    for &root in finished.iter().rev() {
+        if component[root as usize] != usize::MAX {
+            continue;
+        }
+        let id = sizes.len();
+        let mut size = 0;
+        component[root as usize] = id;
+        let mut pending = vec![root];
+        while let Some(node) = pending.pop() {
+            size += 1;
+            for edge in arriving.of(node) {
+                if component[edge.to as usize] == usize::MAX {
+                    component[edge.to as usize] = id;
+                    pending.push(edge.to);
+                }
+            }
+        }
+        sizes.push(size);
     }
-    pub fn get_segments_from(&self, gc: &GeoCoord) -> Option<&Vec<StreetSegment>> {
-        self.streets.get(gc)
+    let largest = (0..sizes.len()).max_by_key(|&id| sizes[id]);
+    component.iter().map(|&id| Some(id) == largest).collect()
+}
+
+// * Checks the offsets and edges as they're read, so a damaged file can't cause a panic later,
+// * nor ask for more memory than it could possibly describe.
+fn read_adjacency(r: &mut &[u8], node_count: usize, name_count: usize) -> io::Result<Adjacency> {
+    check_fits(r, node_count + 1, 4)?;
+    let mut offsets = Vec::with_capacity(node_count + 1);
+    for _ in 0..=node_count {
+        let offset = read_u32(r)?;
+        if offsets.last().is_some_and(|&last| offset < last) || offsets.is_empty() && offset != 0 {
+            return Err(invalid("adjacency offsets out of order"));
+        }
+        offsets.push(offset);
+    }
+    let edge_count = offsets[node_count] as usize;
+    // Edges are two indices and a length.
+    check_fits(r, edge_count, 16)?;
+    let mut edges = Vec::with_capacity(edge_count);
+    for _ in 0..edge_count {
+        let edge = Edge {
+            to: read_u32(r)?,
+            name: read_u32(r)?,
+            length: read_f64(r)?,
+        };
+        if edge.to as usize >= node_count || edge.name as usize >= name_count {
+            return Err(invalid("edge refers to a missing node or name"));
+        }
+        edges.push(edge);
     }
+    Ok(Adjacency { offsets, edges })
+}
+
+// A file's size and modification time (in nanoseconds since 1970), to tell versions of it apart.
+fn source_stamp(path: &str) -> io::Result<(u64, u64)> {
+    let metadata = std::fs::metadata(path)?;
+    let modified = metadata
+        .modified()?
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_nanos() as u64);
+    Ok((metadata.len(), modified))
+}
+
+fn cell_of(gc: &GeoCoord) -> Cell {
+    (
+        (gc.latitude() / GRID_CELL_DEGREES).floor() as i32,
+        (gc.longitude() / GRID_CELL_DEGREES).floor() as i32,
+    )
+}
+
+// Projects gc onto seg in a local flat approximation, which is plenty at city scale.
+// Returns the fraction along seg and the distance in miles from gc to the projected point.
+fn project_onto(gc: &GeoCoord, seg: &StreetSegment) -> (f64, f64) {
+    let scale = gc.latitude().to_radians().cos();
+    let (sx, sy) = (
+        (seg.start.longitude() - gc.longitude()) * scale,
+        seg.start.latitude() - gc.latitude(),
+    );
+    let (dx, dy) = (
+        (seg.end.longitude() - seg.start.longitude()) * scale,
+        seg.end.latitude() - seg.start.latitude(),
+    );
+    let len_sq = dx * dx + dy * dy;
+    let t = if len_sq == 0. {
+        0.
+    } else {
+        (-(sx * dx + sy * dy) / len_sq).clamp(0., 1.)
+    };
+    let projected = point_along(seg, t);
+    (t, distance_earth_miles(gc, &projected))
+}
+
+fn point_along(seg: &StreetSegment, t: f64) -> GeoCoord {
+    GeoCoord::from_degrees(
+        seg.start.latitude() + t * (seg.end.latitude() - seg.start.latitude()),
+        seg.start.longitude() + t * (seg.end.longitude() - seg.start.longitude()),
+    )
 }
 
 /*
 Recap:
 -if let and while let are meant to be used with let Some().
 */
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::point_router::PointToPointRouter;
+    use std::fs;
+
+    const MAP_DATA: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/mapdata.txt");
+
+    const TWO_STREETS: &str = "Main Street
+1
+34.0600 -118.4400 34.0600 -118.4390
+Side Street
+1
+34.0600 -118.4390 34.0610 -118.4390
+";
+
+    // A file of this test's own in the temp directory, so tests can run side by side.
+    fn temp_file(name: &str) -> String {
+        let name = format!("goober_eats_test_{}_{}", std::process::id(), name);
+        std::env::temp_dir().join(name).to_str().unwrap().to_string()
+    }
+
+    fn parse(map_file: &str) -> StreetMap {
+        let mut sm = StreetMap::new();
+        sm.load(map_file).unwrap();
+        sm
+    }
+
+    fn segments(sm: &StreetMap) -> Vec<StreetSegment> {
+        sm.segments().map(|(start, end, name)| sm.segment(start, end, name)).collect()
+    }
+
+    #[test]
+    fn compiled_maps_route_like_the_text() {
+        let text = temp_file("round_trip.txt");
+        fs::copy(MAP_DATA, &text).unwrap();
+        let compiled = StreetMap::compiled_path(&text);
+        let parsed = parse(&text);
+        parsed.save_compiled(&compiled, &text).unwrap();
+        let loaded = StreetMap::load_compiled(&compiled, Some(source_stamp(&text).unwrap()));
+        // * Compiling the same map again gives the same file, byte for byte.
+        let again = temp_file("round_trip_again.bin");
+        parse(MAP_DATA).save_compiled(&again, &text).unwrap();
+        let same_bytes = fs::read(&compiled).unwrap() == fs::read(&again).unwrap();
+        for path in [&text, &compiled, &again] {
+            fs::remove_file(path).unwrap();
+        }
+        let loaded = loaded.unwrap();
+        assert!(same_bytes);
+
+        assert_eq!(segments(&loaded), segments(&parsed));
+        let mut coords: Vec<GeoCoord> = parsed.coords().cloned().collect();
+        coords.sort();
+        let step = coords.len() / 20;
+        let coords: Vec<GeoCoord> = coords.into_iter().step_by(step).collect();
+        let from_parsed = PointToPointRouter::from(&parsed);
+        let from_loaded = PointToPointRouter::from(&loaded);
+        for start in &coords {
+            for end in &coords {
+                let expected = from_parsed.generate_route(start, end).ok();
+                let actual = from_loaded.generate_route(start, end).ok();
+                assert_eq!(actual, expected, "{:?} to {:?}", start, end);
+            }
+        }
+    }
+
+    #[test]
+    fn bad_compiled_maps_fall_back_to_the_text() {
+        let text = temp_file("fall_back.txt");
+        let compiled = StreetMap::compiled_path(&text);
+        fs::write(&text, TWO_STREETS).unwrap();
+        parse(&text).save_compiled(&compiled, &text).unwrap();
+        let good = fs::read(&compiled).unwrap();
+
+        // * Everything load_compiled has to turn down: a mangled magic number, another format
+        // * version, a count past the end of the file, a cut-off file and one with extra bytes.
+        let mut bad_files = Vec::new();
+        for (at, bytes) in [(0, *b"XXXX"), (4, 99u32.to_le_bytes()), (24, u32::MAX.to_le_bytes())] {
+            let mut bad = good.clone();
+            bad[at..at + 4].copy_from_slice(&bytes);
+            bad_files.push(bad);
+        }
+        bad_files.push(good[..good.len() / 2].to_vec());
+        bad_files.push([&good[..], &[0]].concat());
+        let stamp = Some(source_stamp(&text).unwrap());
+        let mut results = Vec::new();
+        for bad in &bad_files {
+            fs::write(&compiled, bad).unwrap();
+            let refused = StreetMap::load_compiled(&compiled, stamp).is_err();
+            let loaded = StreetMap::load_from(&text).map(|sm| segments(&sm).len());
+            results.push((refused, loaded.ok()));
+        }
+        // * A copy of an older version of the text is out of date, however sound it is.
+        fs::write(&compiled, &good).unwrap();
+        let back_street = "Back Street\n1\n34.0600 -118.4400 34.0610 -118.4400\n";
+        fs::write(&text, format!("{}{}", TWO_STREETS, back_street)).unwrap();
+        let stale = StreetMap::load_from(&text).map(|sm| segments(&sm).len());
+        fs::remove_file(&text).unwrap();
+        fs::remove_file(&compiled).unwrap();
+
+        for (i, result) in results.into_iter().enumerate() {
+            assert_eq!(result, (true, Some(2)), "bad file {}", i);
+        }
+        assert_eq!(stale.ok(), Some(3));
+    }
+}
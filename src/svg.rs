@@ -0,0 +1,153 @@
+use crate::planner::DeliveryPlan;
+use crate::provided::{GeoCoord, StopKind};
+use crate::street_map::StreetMap;
+use crate::xml::escape;
+use std::fmt::Write;
+
+// Width of the drawing in pixels; the height follows from the map's shape.
+const WIDTH: f64 = 1600.;
+const MARGIN: f64 = 20.;
+// Leg colors, used in turn.
+const PALETTE: [&str; 8] = [
+    "#e6194b", "#3cb44b", "#4363d8", "#f58231", "#911eb4", "#42d4f4", "#f032e6", "#9a6324",
+];
+
+// * Equirectangular projection: longitude is scaled by the cosine of the middle latitude, so
+// * a mile is about as long across the drawing as it is down it.  North is up.
+struct Projection {
+    min_lon: f64,
+    max_lat: f64,
+    x_scale: f64,
+    scale: f64,
+    height: f64,
+}
+
+impl Projection {
+    fn fit<'a>(coords: impl Iterator<Item = &'a GeoCoord>) -> Projection {
+        let (mut min_lat, mut max_lat) = (f64::INFINITY, f64::NEG_INFINITY);
+        let (mut min_lon, mut max_lon) = (f64::INFINITY, f64::NEG_INFINITY);
+        for gc in coords {
+            min_lat = min_lat.min(gc.latitude());
+            max_lat = max_lat.max(gc.latitude());
+            min_lon = min_lon.min(gc.longitude());
+            max_lon = max_lon.max(gc.longitude());
+        }
+        if min_lat > max_lat {
+            (min_lat, max_lat, min_lon, max_lon) = (0., 0., 0., 0.);
+        }
+        let x_scale = ((min_lat + max_lat) / 2.).to_radians().cos();
+        let span_x = ((max_lon - min_lon) * x_scale).max(f64::EPSILON);
+        let span_y = max_lat - min_lat;
+        let scale = (WIDTH - 2. * MARGIN) / span_x;
+        Projection {
+            min_lon,
+            max_lat,
+            x_scale,
+            scale,
+            height: span_y * scale + 2. * MARGIN,
+        }
+    }
+    fn point(&self, gc: &GeoCoord) -> (f64, f64) {
+        (
+            MARGIN + (gc.longitude() - self.min_lon) * self.x_scale * self.scale,
+            MARGIN + (self.max_lat - gc.latitude()) * self.scale,
+        )
+    }
+}
+
+// * The whole map in grey, each vehicle's legs on top in colors taken in turn, then the stops
+// * numbered in visiting order (pickups in white) and the depot as a black square.
+pub fn render_svg(street_map: &StreetMap, plans: &[DeliveryPlan]) -> String {
+    let stops = plans.iter().flat_map(|plan| plan.order.iter().map(|stop| &stop.location));
+    let depots = plans.iter().map(|plan| &plan.depot);
+    let projection = Projection::fit(street_map.coords().chain(stops).chain(depots));
+
+    let mut svg = String::new();
+    writeln!(
+        svg,
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{w:.0}\" height=\"{h:.0}\" \
+         viewBox=\"0 0 {w:.0} {h:.0}\">",
+        w = WIDTH,
+        h = projection.height
+    )
+    .unwrap();
+    svg.push_str("<rect width=\"100%\" height=\"100%\" fill=\"white\"/>\n");
+
+    // * Every segment goes into one path, which keeps the file small.
+    svg.push_str("<path fill=\"none\" stroke=\"#bbbbbb\" stroke-width=\"1\" d=\"");
+    for (start, end, _) in street_map.segments() {
+        let (x1, y1) = projection.point(street_map.coord(start));
+        let (x2, y2) = projection.point(street_map.coord(end));
+        write!(svg, "M{:.1} {:.1}L{:.1} {:.1}", x1, y1, x2, y2).unwrap();
+    }
+    svg.push_str("\"/>\n");
+
+    let mut color = 0;
+    for (i, plan) in plans.iter().enumerate() {
+        for (index, leg) in plan.legs.iter().enumerate() {
+            let Some(first) = leg.route.first() else {
+                continue;
+            };
+            let mut points = String::new();
+            for gc in std::iter::once(&first.start).chain(leg.route.iter().map(|seg| &seg.end)) {
+                let (x, y) = projection.point(gc);
+                write!(points, "{:.1},{:.1} ", x, y).unwrap();
+            }
+            writeln!(
+                svg,
+                "<polyline fill=\"none\" stroke=\"{}\" stroke-width=\"3\" points=\"{}\">\
+                 <title>Vehicle {}, leg {}: {:.2} miles</title></polyline>",
+                PALETTE[color % PALETTE.len()],
+                points.trim_end(),
+                i + 1,
+                index,
+                leg.distance
+            )
+            .unwrap();
+            color += 1;
+        }
+    }
+
+    let mut number = 0;
+    for plan in plans {
+        for stop in &plan.order {
+            number += 1;
+            let (x, y) = projection.point(&stop.location);
+            let (fill, text) = match stop.kind {
+                StopKind::Dropoff => ("#222222", "white"),
+                StopKind::Pickup => ("white", "#222222"),
+            };
+            writeln!(
+                svg,
+                "<g><title>{}</title><circle cx=\"{:.1}\" cy=\"{:.1}\" r=\"8\" fill=\"{}\" \
+                 stroke=\"#222222\"/><text x=\"{:.1}\" y=\"{:.1}\" font-size=\"9\" \
+                 font-family=\"sans-serif\" text-anchor=\"middle\" fill=\"{}\">{}</text></g>",
+                escape(&stop.item),
+                x,
+                y,
+                fill,
+                x,
+                y + 3.,
+                text,
+                number
+            )
+            .unwrap();
+        }
+    }
+    if let Some(first) = plans.first() {
+        let (x, y) = projection.point(&first.depot);
+        writeln!(
+            svg,
+            "<g><title>Depot</title><rect x=\"{:.1}\" y=\"{:.1}\" width=\"16\" height=\"16\" \
+             fill=\"black\"/><text x=\"{:.1}\" y=\"{:.1}\" font-size=\"10\" \
+             font-family=\"sans-serif\" text-anchor=\"middle\" fill=\"white\">D</text></g>",
+            x - 8.,
+            y - 8.,
+            x,
+            y + 4.
+        )
+        .unwrap();
+    }
+    svg.push_str("</svg>\n");
+    svg
+}
@@ -0,0 +1,224 @@
+// Node 0 is always the depot, and node i + 1 the ith stop handed to the optimizer.
+pub const DEPOT: usize = 0;
+
+// * Leg costs between every pair of nodes, flattened so lookups don't go through a hash map.
+// * Costs needn't be symmetric: one-way streets make A to B and B to A differ.
+pub struct CostTable {
+    size: usize,
+    costs: Vec<f64>,
+}
+
+impl CostTable {
+    pub fn build(size: usize, cost: impl Fn(usize, usize) -> f64) -> CostTable {
+        let mut costs = Vec::with_capacity(size * size);
+        for from in 0..size {
+            for to in 0..size {
+                costs.push(cost(from, to));
+            }
+        }
+        CostTable { size, costs }
+    }
+    // How many nodes the table covers.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+    pub fn get(&self, from: usize, to: usize) -> f64 {
+        self.costs[from * self.size + to]
+    }
+}
+
+// The ways a tour can be rearranged.  Positions count the depot at the start as 0.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Move {
+    // 2-opt: reverse the stops at positions i..=j.
+    Reverse { i: usize, j: usize },
+    // Or-opt: take len stops starting at position start and put them back after position to.
+    Relocate { start: usize, len: usize, to: usize },
+    // 3-opt: swap the runs of stops at positions i + 1..=j and j + 1..=k, keeping their direction.
+    SwapRuns { i: usize, j: usize, k: usize },
+}
+
+// Or-opt moves runs of at most this many stops.
+const OR_OPT_MAX_LEN: usize = 3;
+
+// * A closed tour out of the depot and back, as node indices.  Running sums of its legs, driven
+// * both forwards and backwards, let every move's change in length be worked out in O(1).
+#[derive(Clone, Debug)]
+pub struct Tour {
+    // Starts and ends with the depot.
+    nodes: Vec<usize>,
+    // forward[p] is the length of nodes[..=p] in tour order; backward[p] drives the same legs the other way.
+    forward: Vec<f64>,
+    backward: Vec<f64>,
+}
+
+impl Tour {
+    // order holds the stops' nodes, without the depot.
+    pub fn new(order: &[usize], costs: &CostTable) -> Tour {
+        let mut nodes = Vec::with_capacity(order.len() + 2);
+        nodes.push(DEPOT);
+        nodes.extend_from_slice(order);
+        nodes.push(DEPOT);
+        let mut tour = Tour {
+            nodes,
+            forward: Vec::new(),
+            backward: Vec::new(),
+        };
+        tour.sum_legs(costs);
+        tour
+    }
+    // The stops in visiting order, without the depot.
+    pub fn order(&self) -> &[usize] {
+        &self.nodes[1..self.nodes.len() - 1]
+    }
+    pub fn length(&self) -> f64 {
+        *self.forward.last().unwrap()
+    }
+    fn stop_count(&self) -> usize {
+        self.nodes.len() - 2
+    }
+    fn sum_legs(&mut self, costs: &CostTable) {
+        let (mut forward, mut backward) = (0., 0.);
+        self.forward.clear();
+        self.backward.clear();
+        self.forward.push(0.);
+        self.backward.push(0.);
+        for leg in self.nodes.windows(2) {
+            forward += costs.get(leg[0], leg[1]);
+            backward += costs.get(leg[1], leg[0]);
+            self.forward.push(forward);
+            self.backward.push(backward);
+        }
+    }
+    // How much longer (or, if negative, shorter) the tour gets by making the move.
+    pub fn delta(&self, mv: &Move, costs: &CostTable) -> f64 {
+        let n = &self.nodes;
+        let c = |a: usize, b: usize| costs.get(n[a], n[b]);
+        match *mv {
+            Move::Reverse { i, j } => {
+                let inside = self.forward[j] - self.forward[i];
+                let reversed = self.backward[j] - self.backward[i];
+                c(i - 1, j) + reversed + c(i, j + 1) - c(i - 1, i) - inside - c(j, j + 1)
+            }
+            Move::Relocate { start, len, to } => {
+                let last = start + len - 1;
+                c(start - 1, last + 1) - c(start - 1, start) - c(last, last + 1) + c(to, start)
+                    + c(last, to + 1)
+                    - c(to, to + 1)
+            }
+            Move::SwapRuns { i, j, k } => {
+                c(i, j + 1) + c(k, i + 1) + c(j, k + 1) - c(i, i + 1) - c(j, j + 1) - c(k, k + 1)
+            }
+        }
+    }
+    pub fn apply(&mut self, mv: &Move, costs: &CostTable) {
+        rearrange(&mut self.nodes, mv);
+        self.sum_legs(costs);
+    }
+    // The stops in the order the move would leave them, for costs that need the whole tour.
+    pub fn order_after(&self, mv: &Move) -> Vec<usize> {
+        let mut nodes = self.nodes.clone();
+        rearrange(&mut nodes, mv);
+        nodes[1..nodes.len() - 1].to_vec()
+    }
+    // * Picks one of the three kinds of move at random.  Tours of fewer than two stops can't change.
+    pub fn random_move(&self, rng: &fastrand::Rng) -> Option<Move> {
+        let n = self.stop_count();
+        if n < 2 {
+            return None;
+        }
+        let mv = match rng.usize(..3) {
+            0 => {
+                let (i, j) = distinct_pair(rng, 1..n + 1);
+                Move::Reverse { i, j }
+            }
+            1 => {
+                let len = rng.usize(1..=OR_OPT_MAX_LEN.min(n - 1));
+                let start = rng.usize(1..=n - len + 1);
+                // Anywhere but just before or inside the run itself.
+                let r = rng.usize(..n - len);
+                let to = if r < start - 1 { r } else { r + len + 1 };
+                Move::Relocate { start, len, to }
+            }
+            _ => {
+                let mut picks = [0; 3];
+                for p in 0..3 {
+                    picks[p] = loop {
+                        let pick = rng.usize(..=n);
+                        if !picks[..p].contains(&pick) {
+                            break pick;
+                        }
+                    };
+                }
+                picks.sort_unstable();
+                let [i, j, k] = picks;
+                Move::SwapRuns { i, j, k }
+            }
+        };
+        Some(mv)
+    }
+}
+
+// Two different numbers from the range, smallest first.  The range must hold at least two.
+fn distinct_pair(rng: &fastrand::Rng, range: std::ops::Range<usize>) -> (usize, usize) {
+    loop {
+        let (a, b) = (rng.usize(range.clone()), rng.usize(range.clone()));
+        if a != b {
+            return (a.min(b), a.max(b));
+        }
+    }
+}
+
+fn rearrange(nodes: &mut [usize], mv: &Move) {
+    match *mv {
+        Move::Reverse { i, j } => nodes[i..=j].reverse(),
+        Move::Relocate { start, len, to } => {
+            if to >= start + len {
+                nodes[start..=to].rotate_left(len);
+            } else {
+                nodes[to + 1..start + len].rotate_right(len);
+            }
+        }
+        Move::SwapRuns { i, j, k } => nodes[i + 1..=k].rotate_left(j - i),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Costs from 0 to 10 that differ in each direction, like a map full of one-way streets.
+    fn random_costs(size: usize, rng: &fastrand::Rng) -> CostTable {
+        let costs: Vec<f64> = (0..size * size).map(|_| rng.f64() * 10.).collect();
+        CostTable::build(size, |from, to| if from == to { 0. } else { costs[from * size + to] })
+    }
+
+    #[test]
+    fn delta_matches_the_rearranged_tour() {
+        let rng = fastrand::Rng::new();
+        rng.seed(42);
+        // Reverses, relocations, relocations one place on, relocations to the front, swaps.
+        let mut seen = [0; 5];
+        for _ in 0..200 {
+            let stops = rng.usize(2..10);
+            let costs = random_costs(stops + 1, &rng);
+            let mut order: Vec<usize> = (1..=stops).collect();
+            rng.shuffle(&mut order);
+            let tour = Tour::new(&order, &costs);
+            for _ in 0..50 {
+                let mv = tour.random_move(&rng).unwrap();
+                let expected = Tour::new(&tour.order_after(&mv), &costs).length() - tour.length();
+                let delta = tour.delta(&mv, &costs);
+                assert!((delta - expected).abs() < 1e-9, "{:?}: {} != {}", mv, delta, expected);
+                seen[match mv {
+                    Move::Reverse { .. } => 0,
+                    Move::Relocate { start, len, to } if to == start + len => 2,
+                    Move::Relocate { to: 0, .. } => 3,
+                    Move::Relocate { .. } => 1,
+                    Move::SwapRuns { .. } => 4,
+                }] += 1;
+            }
+        }
+        assert!(seen.iter().all(|&count| count > 0), "{:?}", seen);
+    }
+}
@@ -0,0 +1,7 @@
+// * Makes text safe to put in XML, both between tags and inside a quoted attribute.
+pub fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}